@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE failed_deliveries(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues(newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL,
+    error TEXT NOT NULL,
+    failed_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,24 @@
+-- Add migration script here
+CREATE TABLE issue_deliveries(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues(newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    status TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    queued_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
+
+-- Tasks which were already queued before deliveries were logged.
+INSERT INTO issue_deliveries(
+    newsletter_issue_id,
+    subscriber_email,
+    n_attempts,
+    status,
+    queued_at,
+    updated_at
+)
+SELECT newsletter_issue_id, subscriber_email, n_retries, 'queued', now(), now()
+FROM issue_delivery_queue;
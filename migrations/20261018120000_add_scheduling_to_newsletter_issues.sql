@@ -0,0 +1,9 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+    UPDATE newsletter_issues SET status = 'published';
+    ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+    ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+    -- Issues which have not gone out yet have no publication date.
+    ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+COMMIT;
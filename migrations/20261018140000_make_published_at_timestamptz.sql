@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz;
@@ -0,0 +1,41 @@
+-- Add migration script here
+BEGIN;
+    CREATE TABLE lists(
+        list_id uuid PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        created_at timestamptz NOT NULL DEFAULT now()
+    );
+
+    -- The list everyone was implicitly subscribed to so far.
+    INSERT INTO lists(list_id, name) VALUES (gen_random_uuid(), 'Newsletter');
+
+    CREATE TABLE list_subscriptions(
+        list_id uuid NOT NULL REFERENCES lists(list_id),
+        subscriber_id uuid NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+        status TEXT NOT NULL,
+        subscribed_at timestamptz NOT NULL,
+        PRIMARY KEY (list_id, subscriber_id)
+    );
+    INSERT INTO list_subscriptions(list_id, subscriber_id, status, subscribed_at)
+    SELECT l.list_id, s.id, s.status, s.subscribed_at
+    FROM subscriptions s, lists l;
+
+    -- Each confirmation link confirms a subscription to a single list.
+    ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL;
+    UPDATE subscription_tokens SET list_id = (SELECT list_id FROM lists);
+    ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+    ALTER TABLE subscription_tokens
+        ADD CONSTRAINT subscription_tokens_list_subscription_fkey
+            FOREIGN KEY (list_id, subscriber_id)
+            REFERENCES list_subscriptions (list_id, subscriber_id) ON DELETE CASCADE;
+
+    CREATE TABLE newsletter_issue_lists(
+        newsletter_issue_id uuid NOT NULL
+            REFERENCES newsletter_issues(newsletter_issue_id),
+        list_id uuid NOT NULL REFERENCES lists(list_id),
+        PRIMARY KEY (newsletter_issue_id, list_id)
+    );
+    INSERT INTO newsletter_issue_lists(newsletter_issue_id, list_id)
+    SELECT i.newsletter_issue_id, l.list_id
+    FROM newsletter_issues i, lists l;
+COMMIT;
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, key)
+);
+CREATE INDEX subscriber_tags_key_value ON subscriber_tags (key, value);
+
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT;
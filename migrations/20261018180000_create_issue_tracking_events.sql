@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE issue_tracking_events(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+    url TEXT,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX issue_tracking_events_issue_kind ON issue_tracking_events (newsletter_issue_id, kind);
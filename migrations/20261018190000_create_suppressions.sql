@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE suppressions(
+    email TEXT NOT NULL PRIMARY KEY,
+    reason TEXT NOT NULL,
+    suppressed_at timestamptz NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Existing users could do everything, so they stay admins. New users start with the least access.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin'
+    CHECK (role IN ('admin', 'editor', 'viewer'));
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT false;
+
+-- Only a hash of each invitation token is stored, so that the links cannot be rebuilt from the
+-- database. Inviting the same address again replaces its invitation.
+CREATE TABLE user_invitations (
+    email TEXT PRIMARY KEY,
+    role TEXT NOT NULL CHECK (role IN ('admin', 'editor', 'viewer')),
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Sessions remember the version they were opened with, so bumping it logs the user out of all of
+-- them, e.g. when their password is reset.
+ALTER TABLE users ADD COLUMN session_version INTEGER NOT NULL DEFAULT 0;
+
+-- Only a hash of each token is stored, like for invitations.
+CREATE TABLE password_reset_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL
+);
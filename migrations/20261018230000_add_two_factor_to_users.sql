@@ -0,0 +1,12 @@
+-- Add migration script here
+-- The base32 secret of the user's authenticator, once they have set one up.
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+-- The last time step a code was accepted for, so that each code works once.
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT NULL;
+
+-- Hashed like other tokens.
+CREATE TABLE totp_recovery_codes (
+    user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -1,20 +1,33 @@
 use std::ops::Deref;
 
 use actix_web::{
-    FromRequest, HttpMessage,
-    body::MessageBody,
+    FromRequest, HttpMessage, HttpResponse,
+    body::{EitherBody, MessageBody},
     dev::{ServiceRequest, ServiceResponse},
     error::InternalError,
+    http::header::ContentType,
     middleware::Next,
+    web,
 };
-use anyhow::anyhow;
+use actix_web_flash_messages::FlashMessage;
+use anyhow::{Context, anyhow};
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::Role;
 use crate::{
     session_state::TypedSession,
     util::{e500, see_other},
 };
 
+/// Let logged in users through, with their id and role available to handlers as [`UserId`] and
+/// [`CurrentUser`].
+///
+/// The role is read on every request, so that a change applies right away. Likewise, disabling or
+/// deleting a user, or [logging them out everywhere](super::log_out_everywhere), logs them out.
+///
+/// Users who have entered their password but not their second factor yet are sent back to enter
+/// it.
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
@@ -23,17 +36,118 @@ pub async fn reject_anonymous_users(
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
-    match session.get_user_id().map_err(e500)? {
-        Some(uuid) => {
-            req.extensions_mut().insert(UserId(uuid));
-            next.call(req).await
-        }
-        None => {
-            let response = see_other("/login");
-            let e = anyhow!("The user has not logged in.");
-            Err(InternalError::from_response(e, response).into())
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        if session
+            .get_half_authenticated_user_id()
+            .map_err(e500)?
+            .is_some()
+        {
+            return Err(InternalError::from_response(
+                anyhow!("The user has not entered their second factor."),
+                see_other("/login/two_factor"),
+            )
+            .into());
         }
+        return Err(redirect_to_login("The user has not logged in."));
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| e500("The database pool is missing"))?;
+    let Some(user) = get_user(pool, user_id).await.map_err(e500)? else {
+        session.log_out();
+        return Err(redirect_to_login("The user no longer exists."));
+    };
+    if user.disabled {
+        session.log_out();
+        return Err(redirect_to_login("The user has been disabled."));
+    }
+    if session.get_session_version().map_err(e500)? != user.session_version {
+        session.log_out();
+        return Err(redirect_to_login("The session is no longer valid."));
+    }
+    let role = user.role;
+
+    let user_id = UserId(user_id);
+    req.extensions_mut().insert(user_id);
+    req.extensions_mut().insert(CurrentUser { user_id, role });
+    next.call(req).await
+}
+
+/// Only let editors and admins through, e.g. to publish issues.
+///
+/// Must be used within [`reject_anonymous_users`].
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Only let admins through, e.g. to manage users.
+///
+/// Must be used within [`reject_anonymous_users`].
+pub async fn require_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Admin, req, next).await
+}
+
+async fn require_role<B: MessageBody + 'static>(
+    role: Role,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let allowed = req
+        .extensions()
+        .get::<CurrentUser>()
+        .is_some_and(|user| user.role >= role);
+    if allowed {
+        return Ok(next.call(req).await?.map_into_left_body());
     }
+
+    let message = match role {
+        Role::Admin => "Only admins can do this.",
+        Role::Editor | Role::Viewer => "Only editors and admins can do this.",
+    };
+    FlashMessage::error(message).send();
+    let response = HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<p>{message}</p><p><a href="/admin/dashboard">Back to the dashboard</a></p>"#
+        ));
+    // Responding with an error would drop the flash message.
+    tracing::warn!("The user does not have the {role} role");
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+fn redirect_to_login(reason: &'static str) -> actix_web::Error {
+    InternalError::from_response(anyhow!(reason), see_other("/login")).into()
+}
+
+struct StoredUser {
+    role: Role,
+    disabled: bool,
+    session_version: i32,
+}
+
+#[tracing::instrument(name = "Get user role", skip(pool))]
+async fn get_user(pool: &PgPool, user_id: Uuid) -> Result<Option<StoredUser>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT role, disabled, session_version FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the role of the user")?;
+    row.map(|r| {
+        Ok(StoredUser {
+            role: Role::parse(&r.role).map_err(anyhow::Error::msg)?,
+            disabled: r.disabled,
+            session_version: r.session_version,
+        })
+    })
+    .transpose()
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -51,3 +165,10 @@ impl Deref for UserId {
         &self.0
     }
 }
+
+/// The logged in user, for handlers which depend on what they are allowed to do.
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentUser {
+    pub user_id: UserId,
+    pub role: Role,
+}
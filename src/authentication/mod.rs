@@ -1,5 +1,18 @@
 mod middleware;
 mod password;
+mod role;
+mod sessions;
+mod token;
+mod two_factor;
 
-pub use middleware::{UserId, reject_anonymous_users};
-pub use password::{AuthError, Credentials, change_password, validate_credentials};
+pub use middleware::{CurrentUser, UserId, reject_anonymous_users, require_admin, require_editor};
+pub use password::{
+    AuthError, Credentials, change_password, compute_password_hash, validate_credentials,
+};
+pub use role::Role;
+pub use sessions::{get_session_version, log_out_everywhere};
+pub use token::{generate_token, hash_token};
+pub use two_factor::{
+    SecondFactor, Totp, count_recovery_codes, disable_two_factor, enable_two_factor,
+    has_second_factor, replace_recovery_codes, verify_second_factor,
+};
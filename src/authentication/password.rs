@@ -2,7 +2,7 @@ use anyhow::{Context, anyhow};
 use argon2::PasswordHasher;
 use argon2::PasswordVerifier;
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 
 use crate::telemetry::spawn_blocking_with_async;
@@ -29,18 +29,24 @@ pub async fn validate_credentials(
     // exist.
     let mut password_hash = Secret::new("$argon2id$v=19$m=19456,t=2,p=1$vNXfNE0l0bV2e1R7vDhL8w$uvhiLTsidsTzLUUYFHJbjZ5AKEMDEySRhwVZFcehFWs".to_owned());
     let mut user_id = None;
+    let mut disabled = false;
 
-    if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(db_pool, &credentials.username).await?
-    {
-        user_id = Some(stored_user_id);
-        password_hash = stored_password_hash
+    if let Some(stored) = get_stored_credentials(db_pool, &credentials.username).await? {
+        user_id = Some(stored.user_id);
+        password_hash = stored.password_hash;
+        disabled = stored.disabled;
     }
 
     spawn_blocking_with_async(|| verify_password_hash(password_hash, credentials.password))
         .await
         .context("Failed to spawn blocking task")??;
 
+    // Checked after the password, so that the response does not tell whether an account is
+    // disabled to someone who does not know its password.
+    if disabled {
+        return Err(AuthError::AuthError(anyhow!("The user is disabled")));
+    }
+
     // This should only be some if the username was found in the database.
     // We will never get to this point anyways unless the password given is somehow the random
     // password used above.
@@ -70,7 +76,10 @@ fn verify_password_hash(
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+/// Hash a password to store it in the `users` table.
+///
+/// This is slow on purpose, so call it through [`spawn_blocking_with_async`].
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
     let argon2 = argon2::Argon2::new(
         argon2::Algorithm::Argon2id,
         argon2::Version::V0x13,
@@ -87,28 +96,38 @@ fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, any
     Ok(hash)
 }
 
+struct StoredCredentials {
+    user_id: Uuid,
+    password_hash: Secret<String>,
+    disabled: bool,
+}
+
 #[tracing::instrument(name = "Get stored crdentials", skip(username, db_pool))]
 async fn get_stored_credentials(
     db_pool: &PgPool,
     username: &str,
-) -> Result<Option<(Uuid, Secret<String>)>, AuthError> {
+) -> Result<Option<StoredCredentials>, AuthError> {
     let row = sqlx::query!(
-        "SELECT user_id, password_hash FROM users WHERE username = $1",
+        "SELECT user_id, password_hash, disabled FROM users WHERE username = $1",
         username,
     )
     .fetch_optional(db_pool)
     .await
     .context("Failed to retrieve stored credentials from database")?
-    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    .map(|row| StoredCredentials {
+        user_id: row.user_id,
+        password_hash: Secret::new(row.password_hash),
+        disabled: row.disabled,
+    });
 
     Ok(row)
 }
 
-#[tracing::instrument(name = "Change password", skip(new_password, pool))]
+#[tracing::instrument(name = "Change password", skip(new_password, executor))]
 pub async fn change_password(
     user_id: Uuid,
     new_password: Secret<String>,
-    pool: &PgPool,
+    executor: impl PgExecutor<'_>,
 ) -> Result<(), anyhow::Error> {
     let hash = spawn_blocking_with_async(move || compute_password_hash(new_password))
         .await?
@@ -118,7 +137,7 @@ pub async fn change_password(
         hash.expose_secret(),
         user_id
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .context("Failed to change user's password in the database")?;
     Ok(())
@@ -0,0 +1,58 @@
+/// What a user is allowed to do in the admin area. Each role can do everything the previous ones
+/// can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Can look at issues, reports and subscribers but not change anything.
+    Viewer,
+    /// Can also write and publish issues and manage subscribers and lists.
+    Editor,
+    /// Can also manage users and delete subscribers.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Editor, Role::Admin];
+
+    pub fn parse(role: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|r| r.as_str() == role)
+            .ok_or_else(|| format!("'{role}' is not a role"))
+    }
+
+    /// How the role is stored in the `users` table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn roles_are_parsed_from_how_they_are_stored() {
+        for role in Role::ALL {
+            assert_ok_eq!(Role::parse(role.as_str()), role);
+        }
+        assert_err!(Role::parse("Admin"));
+        assert_err!(Role::parse("owner"));
+    }
+
+    #[test]
+    fn admins_can_do_what_editors_can() {
+        assert!(Role::Admin > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+}
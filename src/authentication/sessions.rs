@@ -0,0 +1,38 @@
+use anyhow::Context;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// The version of the user's sessions, stored in each session when it is opened.
+///
+/// [`reject_anonymous_users`](super::reject_anonymous_users) only lets sessions with the current
+/// version through.
+#[tracing::instrument(name = "Get session version", skip(executor))]
+pub async fn get_session_version(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<i32, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT session_version FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to fetch the session version of the user")?;
+    Ok(row.session_version)
+}
+
+/// Log the user out of every session they have open, wherever it was opened.
+#[tracing::instrument(name = "Log out everywhere", skip(executor))]
+pub async fn log_out_everywhere(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET session_version = session_version + 1 WHERE user_id = $1",
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to invalidate the sessions of the user")?;
+    Ok(())
+}
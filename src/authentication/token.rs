@@ -0,0 +1,34 @@
+use rand::{Rng, distributions::Alphanumeric};
+use sha2::{Digest, Sha256};
+
+/// A random token for a link which lets someone act on an account without logging in, such as an
+/// invitation.
+pub fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// What is stored in place of a token, so that someone reading the database cannot use the links
+/// which are still valid.
+///
+/// The tokens are random enough that a salt or a slow hash would not add anything.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_token, hash_token};
+
+    #[test]
+    fn tokens_are_unique_and_hashed_consistently() {
+        let token = generate_token();
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), hash_token(&generate_token()));
+        assert_ne!(hash_token(&token), token);
+    }
+}
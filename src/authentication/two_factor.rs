@@ -0,0 +1,341 @@
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, Utc};
+use rand::{Rng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+use super::hash_token;
+
+/// What authenticator apps list the codes under.
+const ISSUER: &str = "zero2prod";
+/// How long each code is shown for, in seconds.
+const STEP: i64 = 30;
+/// How many steps before and after the current one a code is still accepted for, to allow for
+/// the clock of the authenticator being slightly off.
+const SKEW: i64 = 1;
+const N_RECOVERY_CODES: usize = 10;
+
+/// The authenticator of a user, which shows a new six digit code every 30 seconds (RFC 6238).
+pub struct Totp(TOTP);
+
+impl Totp {
+    /// A new random secret, base32 encoded like authenticator apps expect it.
+    pub fn generate_secret() -> Secret<String> {
+        let mut bytes = vec![0; 20];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Secret::new(totp_rs::Secret::Raw(bytes).to_encoded().to_string())
+    }
+
+    pub fn new(secret: &Secret<String>, account_name: &str) -> Result<Self, anyhow::Error> {
+        let secret = totp_rs::Secret::Encoded(secret.expose_secret().clone())
+            .to_bytes()
+            .map_err(|e| anyhow!("Invalid TOTP secret: {e:?}"))?;
+        // The skew is applied in `verify`, to know which step a code was for.
+        Ok(Self(TOTP::new_unchecked(
+            Algorithm::SHA1,
+            6,
+            0,
+            STEP as u64,
+            secret,
+            Some(ISSUER.into()),
+            account_name.into(),
+        )))
+    }
+
+    /// The `otpauth://` URI which adds the authenticator to an app, usually through a QR code.
+    pub fn uri(&self) -> String {
+        self.0.get_url()
+    }
+
+    /// The code shown at `time`.
+    pub fn generate(&self, time: DateTime<Utc>) -> String {
+        self.0.generate(time.timestamp() as u64)
+    }
+
+    /// The step `code` was shown for, if it is valid at `time`.
+    ///
+    /// Callers keep the last step a code was accepted for, so that the same code cannot be used
+    /// twice.
+    pub fn verify(&self, code: &str, time: DateTime<Utc>) -> Option<i64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        let current = time.timestamp().div_euclid(STEP);
+        ((current - SKEW).max(0)..=current + SKEW)
+            .find(|step| self.0.check(&code, (step * STEP) as u64))
+    }
+}
+
+/// Codes to log in with instead of the authenticator when it is lost, each of which works once.
+///
+/// They are long enough to be stored with [`hash_token`](super::hash_token) like other tokens.
+fn generate_recovery_codes() -> Vec<String> {
+    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    let mut rng = rand::thread_rng();
+    (0..N_RECOVERY_CODES)
+        .map(|_| {
+            let chars: Vec<char> = (0..16)
+                .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+                .collect();
+            chars
+                .chunks(4)
+                .map(|group| group.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// The form a recovery code is hashed in, whatever the case or separators it was typed with.
+fn normalize_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    code.as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Whether the user has to enter a code from their authenticator after their password.
+#[tracing::instrument(name = "Check for a second factor", skip(executor))]
+pub async fn has_second_factor(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT totp_secret IS NOT NULL AS \"enabled!\" FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to check whether the user has a second factor")?;
+    Ok(row.enabled)
+}
+
+/// How many of their recovery codes the user has not used yet.
+#[tracing::instrument(name = "Count recovery codes", skip(executor))]
+pub async fn count_recovery_codes(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to count recovery codes")?;
+    Ok(row.count)
+}
+
+/// What a code entered as a second factor turned out to be.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SecondFactor {
+    Totp,
+    /// A recovery code, which cannot be used again.
+    RecoveryCode {
+        remaining: i64,
+    },
+    Invalid,
+}
+
+/// Check a code from the user's authenticator, or one of their recovery codes.
+///
+/// A code from the authenticator is only accepted once, and only if it is newer than the last one
+/// accepted, so that someone looking over the user's shoulder cannot use it as well.
+#[tracing::instrument(name = "Verify second factor", skip(pool, code))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+    time: DateTime<Utc>,
+) -> Result<SecondFactor, anyhow::Error> {
+    let row = sqlx::query!("SELECT totp_secret FROM users WHERE user_id = $1", user_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch the TOTP secret of the user")?;
+    let Some(secret) = row.and_then(|r| r.totp_secret).map(Secret::new) else {
+        return Ok(SecondFactor::Invalid);
+    };
+
+    if let Some(step) = Totp::new(&secret, "")?.verify(code, time) {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET totp_last_step = $1
+            WHERE user_id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)
+            "#,
+            step,
+            user_id
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the TOTP step")?;
+        return Ok(if result.rows_affected() == 1 {
+            SecondFactor::Totp
+        } else {
+            SecondFactor::Invalid
+        });
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash = $2",
+        user_id,
+        hash_token(&normalize_recovery_code(code))
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code")?;
+    if result.rows_affected() == 0 {
+        return Ok(SecondFactor::Invalid);
+    }
+    let remaining = count_recovery_codes(pool, user_id).await?;
+    Ok(SecondFactor::RecoveryCode { remaining })
+}
+
+/// Require a code from the authenticator with `secret` after the password from now on.
+///
+/// `step` is the one the code the user confirmed the authenticator with was shown for, so that it
+/// cannot be used to log in. Returns the new recovery codes, to show to the user once.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(transaction, secret))]
+pub async fn enable_two_factor(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    secret: &Secret<String>,
+    step: i64,
+) -> Result<Vec<String>, anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET totp_secret = $1, totp_last_step = $2 WHERE user_id = $3",
+        secret.expose_secret(),
+        step,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the TOTP secret")?;
+    replace_recovery_codes(transaction, user_id).await
+}
+
+/// Replace the recovery codes of the user with new ones, which are returned to show to the user
+/// once.
+#[tracing::instrument(name = "Replace recovery codes", skip(transaction))]
+pub async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete recovery codes")?;
+    let codes = generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|code| hash_token(code)).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::text[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store recovery codes")?;
+    Ok(codes)
+}
+
+/// Go back to logging in with the password alone.
+#[tracing::instrument(name = "Disable two-factor authentication", skip(transaction))]
+pub async fn disable_two_factor(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET totp_secret = NULL, totp_last_step = NULL WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to remove the TOTP secret")?;
+    sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete recovery codes")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Totp, generate_recovery_codes, normalize_recovery_code};
+    use chrono::DateTime;
+    use claims::{assert_none, assert_some_eq};
+    use secrecy::{ExposeSecret, Secret};
+
+    /// The SHA-1 secret of RFC 6238, "12345678901234567890" in base32.
+    fn rfc_totp() -> Totp {
+        let secret = Secret::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string());
+        Totp::new(&secret, "ursula").unwrap()
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        // The last six digits of the eight digit codes in appendix B.
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            let time = DateTime::from_timestamp(time, 0).unwrap();
+            assert_eq!(rfc_totp().generate(time), code);
+        }
+    }
+
+    #[test]
+    fn codes_are_accepted_for_one_step_either_way() {
+        let totp = rfc_totp();
+        let time = DateTime::from_timestamp(1111111109, 0).unwrap();
+        let step = 1111111109 / 30;
+
+        assert_some_eq!(totp.verify("081804", time), step);
+        assert_some_eq!(totp.verify(" 081 804 ", time), step);
+        let later = DateTime::from_timestamp(1111111109 + 30, 0).unwrap();
+        assert_some_eq!(totp.verify("081804", later), step);
+        let much_later = DateTime::from_timestamp(1111111109 + 60, 0).unwrap();
+        assert_none!(totp.verify("081804", much_later));
+        assert_none!(totp.verify("000000", time));
+    }
+
+    #[test]
+    fn the_uri_holds_the_secret_and_the_issuer() {
+        let secret = Totp::generate_secret();
+        let totp = Totp::new(&secret, "ursula").unwrap();
+        let uri = totp.uri();
+        assert!(uri.starts_with("otpauth://totp/zero2prod:ursula?"));
+        assert!(uri.contains(&format!("secret={}", secret.expose_secret())));
+        assert!(uri.contains("issuer=zero2prod"));
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized_to_how_they_are_shown() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        for code in &codes {
+            assert_eq!(code.len(), 19);
+            assert_eq!(&normalize_recovery_code(code), code);
+            assert_eq!(
+                &normalize_recovery_code(&code.to_uppercase().replace('-', " ")),
+                code
+            );
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Where handlers get the time from when what they accept depends on it, such as one-time codes,
+/// so that tests can choose it.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    System,
+    /// Stays at the time it was last set to.
+    Manual(Arc<Mutex<DateTime<Utc>>>),
+}
+
+impl Clock {
+    pub fn manual(now: DateTime<Utc>) -> Self {
+        Self::Manual(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Self::System => Utc::now(),
+            Self::Manual(now) => *now.lock().unwrap(),
+        }
+    }
+
+    /// Move a manual clock forward. The system clock cannot be moved.
+    pub fn advance(&self, by: TimeDelta) {
+        match self {
+            Self::System => panic!("The system clock cannot be moved"),
+            Self::Manual(now) => *now.lock().unwrap() += by,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Clock;
+    use chrono::{TimeDelta, Utc};
+
+    #[test]
+    fn manual_clocks_only_move_when_advanced() {
+        let start = Utc::now();
+        let clock = Clock::manual(start);
+        let copy = clock.clone();
+        assert_eq!(clock.now(), start);
+
+        copy.advance(TimeDelta::seconds(30));
+        assert_eq!(clock.now(), start + TimeDelta::seconds(30));
+    }
+}
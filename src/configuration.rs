@@ -1,11 +1,14 @@
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailTransport, FileSinkEmailClient, PostmarkEmailClient, SmtpEmailClient},
+};
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{
     ConnectOptions,
     postgres::{PgConnectOptions, PgSslMode},
 };
-use std::{convert::TryFrom, time::Duration};
+use std::{convert::TryFrom, num::NonZeroU32, sync::Arc, time::Duration};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -13,6 +16,9 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub delivery: DeliverySettings,
+    pub subscriptions: SubscriptionSettings,
+    pub webhooks: WebhookSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -22,6 +28,8 @@ pub struct ApplicationSettings {
     pub base_url: String,
     pub hmac_secret: Secret<String>,
     pub idempotency_expiry: Duration,
+    /// How long in-flight requests and deliveries get to finish once shutdown starts.
+    pub shutdown_deadline: Duration,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -59,10 +67,36 @@ impl DatabaseSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    /// The backend emails are sent through.
+    #[serde(default)]
+    pub kind: EmailClientKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// Required when `kind` is `smtp`.
+    pub smtp: Option<SmtpSettings>,
+    /// The directory `.eml` files are written to. Required when `kind` is `file`.
+    pub file_sink_directory: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailClientKind {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub require_tls: bool,
 }
 
 impl EmailClientSettings {
@@ -74,13 +108,108 @@ impl EmailClientSettings {
         Duration::from_millis(self.timeout_milliseconds)
     }
 
-    pub fn client(self) -> EmailClient {
+    pub fn client(self) -> Arc<dyn EmailTransport> {
         let sender = self.sender().expect("Invalid configuration");
         let timeout = self.timeout();
-        EmailClient::new(self.base_url, sender, self.authorization_token, timeout)
+        match self.kind {
+            EmailClientKind::Postmark => Arc::new(PostmarkEmailClient::new(
+                self.base_url,
+                sender,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailClientKind::Smtp => {
+                let smtp = self.smtp.expect("Missing smtp settings");
+                let credentials = smtp.username.zip(smtp.password);
+                Arc::new(
+                    SmtpEmailClient::new(
+                        &smtp.host,
+                        smtp.port,
+                        credentials,
+                        smtp.require_tls,
+                        sender,
+                        timeout,
+                    )
+                    .expect("Invalid smtp settings"),
+                )
+            }
+            EmailClientKind::File => {
+                let directory = self
+                    .file_sink_directory
+                    .expect("Missing file sink directory");
+                Arc::new(
+                    FileSinkEmailClient::new(directory.into(), sender)
+                        .expect("Failed to create the file sink directory"),
+                )
+            }
+        }
     }
 }
 
+#[derive(serde::Deserialize, Clone)]
+pub struct DeliverySettings {
+    /// How many workers deliver issues concurrently.
+    pub n_workers: u16,
+    /// How many subscribers an issue is sent to at once.
+    pub batch_size: u16,
+    /// The most emails sent per second by all workers together. Unlimited when unset.
+    pub max_emails_per_second: Option<NonZeroU32>,
+    /// How long a worker waits before checking the queue again once it is empty.
+    pub idle_backoff_milliseconds: u64,
+    /// How long a worker waits after failing to process a batch.
+    pub error_backoff_milliseconds: u64,
+    /// How many times delivering an issue to a subscriber is attempted before giving up.
+    pub max_attempts: u16,
+    pub retry_base_delay_milliseconds: u64,
+    pub retry_max_delay_milliseconds: u64,
+    /// Whether issues can track opens and clicks at all. Each issue still has to opt in.
+    pub tracking_enabled: bool,
+}
+
+impl DeliverySettings {
+    pub fn retry_base_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_base_delay_milliseconds)
+    }
+
+    pub fn retry_max_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_max_delay_milliseconds)
+    }
+
+    pub fn idle_backoff(&self) -> Duration {
+        Duration::from_millis(self.idle_backoff_milliseconds)
+    }
+
+    pub fn error_backoff(&self) -> Duration {
+        Duration::from_millis(self.error_backoff_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    /// How long a confirmation link can be used after it was sent.
+    pub confirmation_expiry_hours: u64,
+    /// How long a subscriber who never confirmed is kept around before being deleted.
+    pub pending_expiry_days: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_expiry(&self) -> Duration {
+        Duration::from_secs(self.confirmation_expiry_hours * 60 * 60)
+    }
+
+    pub fn pending_expiry(&self) -> Duration {
+        Duration::from_secs(self.pending_expiry_days * 24 * 60 * 60)
+    }
+}
+
+/// How the email provider authenticates the events it posts to `/webhooks/email`.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    /// The password of basic auth, or sent on its own in the `X-Webhook-Secret` header.
+    pub secret: Secret<String>,
+}
+
 /// The possible runtime environment for the application
 pub enum Environment {
     Local,
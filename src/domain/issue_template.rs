@@ -0,0 +1,197 @@
+use crate::util::escape_html;
+
+/// The content of an issue with placeholders filled in for each recipient, e.g.
+/// `Hello {{ name | "there" }}!`.
+///
+/// The variables are `name`, `email`, `unsubscribe_url` and `view_in_browser_url`. A value that
+/// is missing, like the name of the reader of the web view, is replaced by the quoted fallback
+/// after `|` if there is one, or by nothing.
+#[derive(Debug, PartialEq)]
+pub struct IssueTemplate(Vec<Part>);
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Variable {
+        variable: Variable,
+        fallback: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Variable {
+    Name,
+    Email,
+    UnsubscribeUrl,
+    ViewInBrowserUrl,
+}
+
+/// The values of the variables for one recipient.
+#[derive(Default)]
+pub struct TemplateValues<'a> {
+    pub name: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub unsubscribe_url: Option<&'a str>,
+    pub view_in_browser_url: Option<&'a str>,
+}
+
+impl IssueTemplate {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = content;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| "A '{{' is never closed with '}}'".to_string())?;
+            parts.push(parse_placeholder(&rest[start + 2..start + end])?);
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Self(parts))
+    }
+
+    /// Render the html part of an issue, escaping the values.
+    pub fn render_html(&self, values: &TemplateValues) -> String {
+        self.render(values, escape_html)
+    }
+
+    /// Render the plain text part of an issue, where values are inserted as they are.
+    pub fn render_text(&self, values: &TemplateValues) -> String {
+        self.render(values, str::to_owned)
+    }
+
+    fn render(&self, values: &TemplateValues, escape: impl Fn(&str) -> String) -> String {
+        let mut rendered = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Variable { variable, fallback } => {
+                    let value = match variable {
+                        Variable::Name => values.name,
+                        Variable::Email => values.email,
+                        Variable::UnsubscribeUrl => values.unsubscribe_url,
+                        Variable::ViewInBrowserUrl => values.view_in_browser_url,
+                    };
+                    let value = value
+                        .filter(|v| !v.trim().is_empty())
+                        .or(fallback.as_deref())
+                        .unwrap_or_default();
+                    rendered.push_str(&escape(value));
+                }
+            }
+        }
+        rendered
+    }
+}
+
+/// Parse what is between `{{` and `}}`.
+fn parse_placeholder(placeholder: &str) -> Result<Part, String> {
+    let (name, fallback) = match placeholder.split_once('|') {
+        Some((name, fallback)) => {
+            let fallback = fallback.trim();
+            let fallback = fallback
+                .strip_prefix('"')
+                .and_then(|f| f.strip_suffix('"'))
+                .filter(|f| !f.contains('"'))
+                .ok_or_else(|| {
+                    format!("The fallback of '{{{{{placeholder}}}}}' must be in double quotes")
+                })?;
+            (name.trim(), Some(fallback.to_owned()))
+        }
+        None => (placeholder.trim(), None),
+    };
+    let variable = match name {
+        "name" => Variable::Name,
+        "email" => Variable::Email,
+        "unsubscribe_url" => Variable::UnsubscribeUrl,
+        "view_in_browser_url" => Variable::ViewInBrowserUrl,
+        _ => {
+            return Err(format!(
+                "Unknown variable '{name}'. The available ones are name, email, \
+                unsubscribe_url and view_in_browser_url"
+            ));
+        }
+    };
+    Ok(Part::Variable { variable, fallback })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IssueTemplate, TemplateValues};
+    use claims::assert_err;
+
+    fn values() -> TemplateValues<'static> {
+        TemplateValues {
+            name: Some("<Ursula>"),
+            email: Some("ursula@example.com"),
+            unsubscribe_url: Some("https://example.com/unsubscribe?a=1&b=2"),
+            view_in_browser_url: Some("https://example.com/issues/1"),
+        }
+    }
+
+    #[test]
+    fn variables_are_replaced() {
+        let template = IssueTemplate::parse(
+            "Hi {{name}}, this is for {{ email }}: {{ view_in_browser_url }} {{unsubscribe_url }}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_text(&values()),
+            "Hi <Ursula>, this is for ursula@example.com: https://example.com/issues/1 \
+            https://example.com/unsubscribe?a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_only() {
+        let template =
+            IssueTemplate::parse(r#"<a href="{{ unsubscribe_url }}">{{ name }}</a>"#).unwrap();
+
+        assert_eq!(
+            template.render_html(&values()),
+            r#"<a href="https://example.com/unsubscribe?a=1&amp;b=2">&lt;Ursula&gt;</a>"#
+        );
+        assert_eq!(
+            template.render_text(&values()),
+            r#"<a href="https://example.com/unsubscribe?a=1&b=2"><Ursula></a>"#
+        );
+    }
+
+    #[test]
+    fn missing_values_use_the_fallback() {
+        let template = IssueTemplate::parse(r#"Hi {{ name | "there" }}{{ email }}!"#).unwrap();
+        let values = TemplateValues {
+            name: Some("  "),
+            ..Default::default()
+        };
+
+        assert_eq!(template.render_html(&values), "Hi there!");
+    }
+
+    #[test]
+    fn content_without_variables_is_unchanged() {
+        let content = "<p>Just {some} text with braces }}</p>";
+        let template = IssueTemplate::parse(content).unwrap();
+
+        assert_eq!(template.render_html(&values()), content);
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for content in [
+            "{{ address }}",
+            "{{ name",
+            "{{}}",
+            "{{ name | there }}",
+            r#"{{ name | "the"re" }}"#,
+        ] {
+            assert_err!(IssueTemplate::parse(content), "{content}");
+        }
+    }
+}
@@ -1,7 +1,15 @@
+mod issue_template;
 mod new_subscriber;
+mod segment;
 mod subscriber_email;
 mod subscriber_name;
+mod tracking_token;
+mod unsubscribe_token;
 
+pub use issue_template::{IssueTemplate, TemplateValues};
 pub use new_subscriber::NewSubscriber;
+pub use segment::{Segment, is_valid_tag_key};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use tracking_token::{TrackedDelivery, TrackingToken};
+pub use unsubscribe_token::UnsubscribeToken;
@@ -0,0 +1,348 @@
+use sqlx::{Postgres, QueryBuilder};
+
+/// The longest expression accepted, to keep the generated query reasonably small.
+const MAX_LENGTH: usize = 1000;
+/// How deeply expressions can be nested, so that parsing cannot exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+/// A filter over subscriber tags picking who receives an issue, e.g.
+/// `plan = pro and (locale = de or locale = at)`.
+///
+/// An expression is made of:
+/// - `key = value` and `key != value`, where a subscriber without the tag is never equal;
+/// - `key` on its own, for subscribers with the tag whatever its value;
+/// - `not`, `and` and `or`, in decreasing order of precedence, and parentheses.
+///
+/// Values containing anything but letters, digits, `_`, `-` and `.` must be put in double
+/// quotes, with `\"` and `\\` as escapes.
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Has(String),
+    Equals(String, String),
+    NotEquals(String, String),
+    Not(Box<Segment>),
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+}
+
+impl Segment {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        if expression.len() > MAX_LENGTH {
+            return Err(format!(
+                "The segment cannot be longer than {MAX_LENGTH} characters"
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+            depth: 0,
+        };
+        let segment = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(segment),
+            Some(token) => Err(format!("Unexpected {token} in the segment")),
+        }
+    }
+
+    /// Append a condition matching the subscribers in the segment. The subscriptions table
+    /// must be in scope as `s`.
+    ///
+    /// Keys and values are always sent as bind parameters, never as part of the query text.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Self::Has(key) => {
+                query.push(
+                    "EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.key = ",
+                );
+                query.push_bind(key.clone());
+                query.push(")");
+            }
+            Self::Equals(key, value) => push_equals(query, key, value),
+            Self::NotEquals(key, value) => {
+                query.push("NOT ");
+                push_equals(query, key, value);
+            }
+            Self::Not(segment) => {
+                query.push("NOT (");
+                segment.push_sql(query);
+                query.push(")");
+            }
+            Self::And(left, right) => push_binary(query, left, "AND", right),
+            Self::Or(left, right) => push_binary(query, left, "OR", right),
+        }
+    }
+}
+
+fn push_equals(query: &mut QueryBuilder<'_, Postgres>, key: &str, value: &str) {
+    query.push("EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.key = ");
+    query.push_bind(key.to_owned());
+    query.push(" AND t.value = ");
+    query.push_bind(value.to_owned());
+    query.push(")");
+}
+
+fn push_binary(
+    query: &mut QueryBuilder<'_, Postgres>,
+    left: &Segment,
+    operator: &str,
+    right: &Segment,
+) {
+    query.push("(");
+    left.push_sql(query);
+    query.push(format_args!(" {operator} "));
+    right.push_sql(query);
+    query.push(")");
+}
+
+/// Whether a tag can be referred to as `key` in a segment.
+pub fn is_valid_tag_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= 64 && key.chars().all(is_word_char) && !is_keyword(key)
+}
+
+/// Whether `c` can be part of a tag key or of a value without quotes.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Equals,
+    NotEquals,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "'{word}'"),
+            Self::Quoted(value) => write!(f, "\"{value}\""),
+            Self::Equals => write!(f, "'='"),
+            Self::NotEquals => write!(f, "'!='"),
+            Self::Open => write!(f, "'('"),
+            Self::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '=' => tokens.push(Token::Equals),
+            '!' if chars.next_if_eq(&'=').is_some() => tokens.push(Token::NotEquals),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => value.push(c),
+                            _ => return Err("Invalid escape in a quoted value".into()),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err("Missing closing quote in the segment".into()),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            c if is_word_char(c) => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("Unexpected '{c}' in the segment")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Consume the next token if it is the keyword `keyword`, whatever its case.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_and()?;
+        while self.keyword("or") {
+            segment = Segment::Or(Box::new(segment), Box::new(self.parse_and()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_not()?;
+        while self.keyword("and") {
+            segment = Segment::And(Box::new(segment), Box::new(self.parse_not()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_not(&mut self) -> Result<Segment, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("The segment is nested too deeply".into());
+        }
+        let segment = if self.keyword("not") {
+            Segment::Not(Box::new(self.parse_not()?))
+        } else {
+            self.parse_condition()?
+        };
+        self.depth -= 1;
+        Ok(segment)
+    }
+
+    fn parse_condition(&mut self) -> Result<Segment, String> {
+        let key = match self.next() {
+            Some(Token::Open) => {
+                let segment = self.parse_or()?;
+                return match self.next() {
+                    Some(Token::Close) => Ok(segment),
+                    _ => Err("Missing closing parenthesis in the segment".into()),
+                };
+            }
+            Some(Token::Word(word)) if !is_keyword(word) => word.clone(),
+            Some(token) => return Err(format!("Expected a tag, found {token}")),
+            None => return Err("The segment ends too early".into()),
+        };
+
+        let equals = match self.peek() {
+            Some(Token::Equals) => true,
+            Some(Token::NotEquals) => false,
+            _ => return Ok(Segment::Has(key)),
+        };
+        self.position += 1;
+        let value = match self.next() {
+            Some(Token::Word(value) | Token::Quoted(value)) => value.clone(),
+            Some(token) => return Err(format!("Expected a value, found {token}")),
+            None => return Err(format!("Missing value for '{key}'")),
+        };
+        Ok(if equals {
+            Segment::Equals(key, value)
+        } else {
+            Segment::NotEquals(key, value)
+        })
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["and", "or", "not"]
+        .iter()
+        .any(|k| word.eq_ignore_ascii_case(k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Segment;
+    use claims::{assert_err, assert_ok_eq};
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn equals(key: &str, value: &str) -> Box<Segment> {
+        Box::new(Segment::Equals(key.into(), value.into()))
+    }
+
+    #[test]
+    fn a_single_condition_is_parsed() {
+        assert_ok_eq!(Segment::parse("plan = pro"), *equals("plan", "pro"));
+        assert_ok_eq!(
+            Segment::parse("plan!=pro"),
+            Segment::NotEquals("plan".into(), "pro".into())
+        );
+        assert_ok_eq!(Segment::parse("beta"), Segment::Has("beta".into()));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_ok_eq!(
+            Segment::parse("a = 1 OR b = 2 and c = 3"),
+            Segment::Or(
+                equals("a", "1"),
+                Box::new(Segment::And(equals("b", "2"), equals("c", "3")))
+            )
+        );
+        assert_ok_eq!(
+            Segment::parse("(a = 1 or b = 2) and not c = 3"),
+            Segment::And(
+                Box::new(Segment::Or(equals("a", "1"), equals("b", "2"))),
+                Box::new(Segment::Not(equals("c", "3")))
+            )
+        );
+    }
+
+    #[test]
+    fn quoted_values_can_contain_anything() {
+        assert_ok_eq!(
+            Segment::parse(r#"company = "O'Reilly \"Media\"""#),
+            *equals("company", r#"O'Reilly "Media""#)
+        );
+    }
+
+    #[test]
+    fn malformed_segments_are_rejected() {
+        for expression in [
+            "",
+            "plan =",
+            "= pro",
+            "plan = pro and",
+            "(plan = pro",
+            "plan = pro)",
+            "plan = \"pro",
+            "plan == pro",
+            "plan = pro; DROP TABLE subscriptions",
+            "and = pro",
+        ] {
+            assert_err!(Segment::parse(expression), "{expression}");
+        }
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let expression = format!("{}a{}", "(".repeat(100), ")".repeat(100));
+        assert_err!(Segment::parse(&expression));
+        let expression = format!("{}a", "not ".repeat(100));
+        assert_err!(Segment::parse(&expression));
+    }
+
+    #[test]
+    fn keys_and_values_are_bound_rather_than_inlined() {
+        let segment = Segment::parse(r#"plan = "pro' OR 1=1 --" or not beta"#).unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("");
+        segment.push_sql(&mut query);
+
+        let sql = query.sql();
+        assert!(!sql.contains("pro"));
+        assert!(!sql.contains("beta"));
+        assert!(sql.contains("$1") && sql.contains("$2") && sql.contains("$3"));
+    }
+}
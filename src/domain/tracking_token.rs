@@ -0,0 +1,185 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A token identifying the delivery of an issue to one subscriber, put in the url of the pixel
+/// tracking opens and of every link tracking clicks.
+///
+/// Like [`UnsubscribeToken`](super::UnsubscribeToken) it is signed rather than stored. A click
+/// token also carries the url of the link, so the redirect can only lead where the issue did.
+#[derive(Debug)]
+pub struct TrackingToken(String);
+
+/// What a verified token was issued for.
+#[derive(Debug, PartialEq)]
+pub struct TrackedDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    /// The link that was followed, for click tokens.
+    pub url: Option<String>,
+}
+
+impl AsRef<str> for TrackingToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TrackingToken {
+    pub fn open(
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        hmac_secret: &Secret<String>,
+    ) -> Self {
+        let tag = mac(newsletter_issue_id, subscriber_id, None, hmac_secret);
+        Self(format!(
+            "{newsletter_issue_id}.{subscriber_id}.{}",
+            hex::encode(tag.finalize().into_bytes())
+        ))
+    }
+
+    pub fn click(
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Self {
+        let tag = mac(newsletter_issue_id, subscriber_id, Some(url), hmac_secret);
+        Self(format!(
+            "{newsletter_issue_id}.{subscriber_id}.{}.{}",
+            URL_SAFE_NO_PAD.encode(url),
+            hex::encode(tag.finalize().into_bytes())
+        ))
+    }
+
+    /// Verify the token and return the delivery it was issued for.
+    pub fn parse(token: &str, hmac_secret: &Secret<String>) -> Result<TrackedDelivery, String> {
+        let malformed = || "Malformed tracking token".to_string();
+        let parts: Vec<_> = token.split('.').collect();
+        let (newsletter_issue_id, subscriber_id, url, tag) = match parts[..] {
+            [issue, subscriber, tag] => (issue, subscriber, None, tag),
+            [issue, subscriber, url, tag] => {
+                let url = URL_SAFE_NO_PAD.decode(url).map_err(|_| malformed())?;
+                let url = String::from_utf8(url).map_err(|_| malformed())?;
+                (issue, subscriber, Some(url), tag)
+            }
+            _ => return Err(malformed()),
+        };
+        let newsletter_issue_id = Uuid::parse_str(newsletter_issue_id).map_err(|_| malformed())?;
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| malformed())?;
+        let tag = hex::decode(tag).map_err(|_| malformed())?;
+
+        mac(
+            newsletter_issue_id,
+            subscriber_id,
+            url.as_deref(),
+            hmac_secret,
+        )
+        .verify_slice(&tag)
+        .map_err(|_| "Invalid tracking token".to_string())?;
+        Ok(TrackedDelivery {
+            newsletter_issue_id,
+            subscriber_id,
+            url,
+        })
+    }
+}
+
+fn mac(
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: Option<&str>,
+    hmac_secret: &Secret<String>,
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take keys of any size");
+    // The prefix keeps open tokens from being passed off as click tokens and the other way round.
+    mac.update(match url {
+        Some(_) => b"tracking-click:",
+        None => b"tracking-open:",
+    });
+    mac.update(newsletter_issue_id.as_bytes());
+    mac.update(subscriber_id.as_bytes());
+    if let Some(url) = url {
+        mac.update(url.as_bytes());
+    }
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrackedDelivery, TrackingToken};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret-key".to_string())
+    }
+
+    #[test]
+    fn generated_tokens_are_valid() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let token = TrackingToken::open(issue_id, subscriber_id, &secret());
+        assert_ok_eq!(
+            TrackingToken::parse(token.as_ref(), &secret()),
+            TrackedDelivery {
+                newsletter_issue_id: issue_id,
+                subscriber_id,
+                url: None
+            }
+        );
+
+        let url = "https://example.com/post?a=1&b=2#top";
+        let token = TrackingToken::click(issue_id, subscriber_id, url, &secret());
+        assert_ok_eq!(
+            TrackingToken::parse(token.as_ref(), &secret()),
+            TrackedDelivery {
+                newsletter_issue_id: issue_id,
+                subscriber_id,
+                url: Some(url.into())
+            }
+        );
+    }
+
+    #[test]
+    fn click_token_for_a_different_url_err() {
+        let token = TrackingToken::click(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "https://example.com",
+            &secret(),
+        );
+        let parts: Vec<_> = token.as_ref().split('.').collect();
+        let forged = format!(
+            "{}.{}.{}.{}",
+            parts[0],
+            parts[1],
+            base64::Engine::encode(
+                &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                "https://evil.example.com"
+            ),
+            parts[3]
+        );
+        assert_err!(TrackingToken::parse(&forged, &secret()));
+    }
+
+    #[test]
+    fn token_signed_with_a_different_secret_err() {
+        let token = TrackingToken::open(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &Secret::new("another-key".to_string()),
+        );
+        assert_err!(TrackingToken::parse(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn malformed_token_err() {
+        assert_err!(TrackingToken::parse("not-a-token", &secret()));
+        assert_err!(TrackingToken::parse("a.b.c.d.e", &secret()));
+    }
+}
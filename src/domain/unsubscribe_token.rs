@@ -0,0 +1,89 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A token that allows a subscriber to leave the newsletter without logging in.
+///
+/// It is made up of the subscriber id and an HMAC tag of that id, so it can be verified without
+/// storing anything and cannot be forged for a different subscriber.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Self {
+        let tag = hex::encode(mac(subscriber_id, hmac_secret).finalize().into_bytes());
+        Self(format!("{subscriber_id}.{tag}"))
+    }
+
+    /// Verify the token and return the id of the subscriber it was issued for.
+    pub fn parse(token: &str, hmac_secret: &Secret<String>) -> Result<Uuid, String> {
+        let (subscriber_id, tag) = token
+            .split_once('.')
+            .ok_or_else(|| "Malformed unsubscribe token".to_string())?;
+        let subscriber_id = Uuid::parse_str(subscriber_id)
+            .map_err(|_| "Malformed unsubscribe token".to_string())?;
+        let tag = hex::decode(tag).map_err(|_| "Malformed unsubscribe token".to_string())?;
+
+        mac(subscriber_id, hmac_secret)
+            .verify_slice(&tag)
+            .map_err(|_| "Invalid unsubscribe token".to_string())?;
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take keys of any size");
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret-key".to_string())
+    }
+
+    #[test]
+    fn generated_token_is_valid() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::parse(token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn token_for_a_different_subscriber_err() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{tag}", Uuid::new_v4());
+        assert_err!(UnsubscribeToken::parse(&forged, &secret()));
+    }
+
+    #[test]
+    fn token_signed_with_a_different_secret_err() {
+        let token =
+            UnsubscribeToken::generate(Uuid::new_v4(), &Secret::new("another-key".to_string()));
+        assert_err!(UnsubscribeToken::parse(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn malformed_token_err() {
+        assert_err!(UnsubscribeToken::parse("not-a-token", &secret()));
+    }
+}
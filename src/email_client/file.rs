@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{EmailTransport, SendEmailError, build_message};
+use crate::domain::SubscriberEmail;
+
+/// Writes every email as an `.eml` file to a directory instead of sending it.
+///
+/// Meant for local development, where the files can be opened with any mail client.
+pub struct FileSinkEmailClient {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkEmailClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(directory),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileSinkEmailClient {
+    /// The returned id is the name of the file the email was written to, minus the extension.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        let id = self
+            .transport
+            .send(message)
+            .await
+            .map_err(|e| SendEmailError::Transient(e.into()))?;
+        Ok(Some(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailTransport, FileSinkEmailClient};
+    use claims::assert_ok;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = FileSinkEmailClient::new(
+            directory.clone(),
+            SubscriberEmail::parse("sender@example.com").unwrap(),
+        )
+        .unwrap();
+
+        let result = email_client
+            .send_email(
+                &SubscriberEmail::parse("recipient@example.com").unwrap(),
+                "Subject",
+                "<p>Html content</p>",
+                "Text content",
+                &[("List-Unsubscribe", "<https://example.com>")],
+            )
+            .await;
+
+        let id = assert_ok!(result).unwrap();
+        let eml = std::fs::read_to_string(directory.join(format!("{id}.eml"))).unwrap();
+        assert!(eml.contains("From: sender@example.com"));
+        assert!(eml.contains("To: recipient@example.com"));
+        assert!(eml.contains("List-Unsubscribe: <https://example.com>"));
+        assert!(eml.contains("Text content"));
+        assert!(eml.contains("<p>Html content</p>"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+mod file;
+mod postmark;
+mod rate_limit;
+mod smtp;
+
+pub use file::FileSinkEmailClient;
+pub use postmark::PostmarkEmailClient;
+pub use rate_limit::RateLimitedEmailClient;
+pub use smtp::SmtpEmailClient;
+
+use lettre::Message;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+
+use crate::domain::SubscriberEmail;
+
+/// A backend the application sends its emails through.
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    /// Send an email, returning the id the backend assigned to it if it reports one.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> SendEmailResult;
+
+    /// Send several emails, returning the outcome of each one in the same order.
+    ///
+    /// Backends without a batch API send the emails one at a time.
+    async fn send_batch(&self, emails: &[Email]) -> Vec<SendEmailResult> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            let result = self
+                .send_email(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    &email.headers(),
+                )
+                .await;
+            results.push(result);
+        }
+        results
+    }
+}
+
+/// An email to send as part of a batch.
+pub struct Email {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Email {
+    fn headers(&self) -> Vec<(&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+}
+
+pub type SendEmailResult = Result<Option<String>, SendEmailError>;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    /// The email might go through if sent again later, e.g. the provider is down or timed out.
+    #[error("Failed to send email due to a transient error")]
+    Transient(#[source] BoxError),
+    /// Sending the same email again will fail in the same way.
+    #[error("Failed to send email")]
+    Permanent(#[source] BoxError),
+}
+
+impl SendEmailError {
+    /// Report the failure of a whole request as the failure of each of the `n` emails in it.
+    fn for_each_email(self, n: usize) -> Vec<SendEmailResult> {
+        let mut causes = Vec::new();
+        let mut source = std::error::Error::source(&self);
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        let message = causes.join(": ");
+        (0..n)
+            .map(|_| {
+                Err(match self {
+                    Self::Transient(_) => Self::Transient(message.clone().into()),
+                    Self::Permanent(_) => Self::Permanent(message.clone().into()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Build an RFC 5322 message for the backends that do not speak a provider specific API.
+fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[(&str, &str)],
+) -> Result<Message, SendEmailError> {
+    let mut builder = Message::builder()
+        .from(mailbox(sender)?)
+        .to(mailbox(recipient)?)
+        .subject(subject)
+        .message_id(None);
+    for &(name, value) in headers {
+        let name = HeaderName::new_from_ascii(name.to_string())
+            .map_err(|e| SendEmailError::Permanent(e.into()))?;
+        builder = builder.raw_header(HeaderValue::new(name, value.to_string()));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_string(),
+            html_content.to_string(),
+        ))
+        .map_err(|e| SendEmailError::Permanent(e.into()))
+}
+
+fn mailbox(email: &SubscriberEmail) -> Result<Mailbox, SendEmailError> {
+    email
+        .as_ref()
+        .parse()
+        .map_err(|e: lettre::address::AddressError| SendEmailError::Permanent(e.into()))
+}
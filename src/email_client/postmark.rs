@@ -0,0 +1,580 @@
+use crate::domain::SubscriberEmail;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailTransport, SendEmailError, SendEmailResult};
+
+/// The most emails Postmark accepts in a single batch request.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Batch error codes caused by the email itself: an invalid request or an inactive recipient.
+/// Others, like the account reaching its sending limit, may clear up later.
+const PERMANENT_ERROR_CODES: &[i64] = &[300, 406];
+
+/// Sends emails through Postmark's `/email` and `/email/batch` APIs.
+pub struct PostmarkEmailClient {
+    client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkEmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            client: Client::builder().timeout(timeout).build().unwrap(),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+
+    fn request_body<'a>(
+        &'a self,
+        recipient: &'a SubscriberEmail,
+        subject: &'a str,
+        html_content: &'a str,
+        text_content: &'a str,
+        headers: &[(&'a str, &'a str)],
+    ) -> SendEmailRequest<'a> {
+        SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            message_stream: "outbound",
+            headers: headers
+                .iter()
+                .map(|&(name, value)| EmailHeader { name, value })
+                .collect(),
+        }
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails in a single request.
+    async fn send_chunk(&self, emails: &[Email]) -> Result<Vec<SendEmailResult>, SendEmailError> {
+        let url = format!("{}/email/batch", self.base_url);
+        let headers: Vec<_> = emails.iter().map(Email::headers).collect();
+        let request_body: Vec<_> = emails
+            .iter()
+            .zip(&headers)
+            .map(|(email, headers)| {
+                self.request_body(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    headers,
+                )
+            })
+            .collect();
+        let response = self
+            .client
+            .post(&url)
+            .json(&request_body)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // Without a readable response there is no telling which emails went through, so they
+        // are all retried rather than risk some never being delivered.
+        let responses = response
+            .json::<Vec<BatchResponse>>()
+            .await
+            .inspect_err(|e| {
+                tracing::warn!(error.cause_chain = ?e, "Failed to parse the email provider's response");
+            })
+            .map_err(|e| SendEmailError::Transient(e.into()))?;
+        let mut responses = responses.into_iter();
+        Ok(emails
+            .iter()
+            .map(|_| match responses.next() {
+                Some(BatchResponse {
+                    error_code: 0,
+                    message_id,
+                    ..
+                }) => Ok(message_id),
+                Some(BatchResponse {
+                    error_code,
+                    message,
+                    ..
+                }) => {
+                    let e = PostmarkError {
+                        error_code,
+                        message,
+                    };
+                    if PERMANENT_ERROR_CODES.contains(&error_code) {
+                        Err(SendEmailError::Permanent(e.into()))
+                    } else {
+                        Err(SendEmailError::Transient(e.into()))
+                    }
+                }
+                None => Err(SendEmailError::Transient(
+                    "The email provider did not report on this email".into(),
+                )),
+            })
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendEmailError> {
+        let url = format!("{}/email", self.base_url);
+        let request_body =
+            self.request_body(recipient, subject, html_content, text_content, headers);
+        let response = self
+            .client
+            .post(&url)
+            .json(&request_body)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // The email has been accepted at this point, so an unexpected body must not be reported
+        // as a failure to send it.
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .inspect_err(|e| {
+                tracing::warn!(error.cause_chain = ?e, "Failed to parse the email provider's response");
+            })
+            .ok()
+            .map(|r| r.message_id);
+        Ok(message_id)
+    }
+
+    async fn send_batch(&self, emails: &[Email]) -> Vec<SendEmailResult> {
+        // There is nothing to gain from the batch endpoint for a single email.
+        if let [email] = emails {
+            let result = self
+                .send_email(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    &email.headers(),
+                )
+                .await;
+            return vec![result];
+        }
+
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_results) => results.extend(chunk_results),
+                Err(e) => results.extend(e.for_each_email(chunk.len())),
+            }
+        }
+        results
+    }
+}
+
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            // Being rate limited is the only client error which is not the request's own fault.
+            Some(status) if status == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                Self::Transient(e.into())
+            }
+            Some(status) if status.is_client_error() => Self::Permanent(e.into()),
+            _ if e.is_builder() => Self::Permanent(e.into()),
+            _ => Self::Transient(e.into()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    text_body: &'a str,
+    html_body: &'a str,
+    message_stream: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResponse {
+    error_code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Postmark rejected the email with error code {error_code}: {message}")]
+struct PostmarkError {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{Email, EmailTransport, PostmarkEmailClient, SendEmailError};
+    use claims::{assert_matches, assert_ok, assert_ok_eq};
+    use fake::{
+        Fake, Faker,
+        faker::{internet::en::SafeEmail, lorem::en::Paragraph, lorem::en::Sentence},
+    };
+    use secrecy::Secret;
+    use wiremock::matchers::any;
+    use wiremock::{
+        Mock, MockServer, Request, ResponseTemplate,
+        matchers::{body_partial_json, header, header_exists, method, path},
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            if let Ok(body) = serde_json::from_slice::<serde_json::Value>(&request.body) {
+                // Check whether required fields exist
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+                    && body.get("MessageStream").is_some()
+            } else {
+                // parsing failed
+                false
+            }
+        }
+    }
+
+    /// Accepts every email of a batch, however many there are.
+    struct AcceptBatch;
+
+    impl wiremock::Respond for AcceptBatch {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let responses: Vec<_> = emails
+                .iter()
+                .map(|_| serde_json::json!({"ErrorCode": 0, "Message": "OK", "MessageID": "id"}))
+                .collect();
+            ResponseTemplate::new(200).set_body_json(responses)
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake::<String>()).unwrap()
+    }
+
+    fn batch(n: usize) -> Vec<Email> {
+        (0..n)
+            .map(|_| Email {
+                recipient: email(),
+                subject: subject(),
+                html_content: content(),
+                text_content: content(),
+                headers: vec![],
+            })
+            .collect()
+    }
+
+    fn email_client(base_url: String) -> PostmarkEmailClient {
+        PostmarkEmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &content(), &content(), &content(), &[])
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_forwards_custom_headers() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://example.com>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[("List-Unsubscribe", "<https://example.com>")],
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_provider_message_id() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "SubmittedAt": "2014-02-17T07:25:01.4178645-05:00",
+                "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+                "ErrorCode": 0,
+                "Message": "OK"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        assert_ok_eq!(
+            result,
+            Some("0a129aee-e1cd-480d-b08d-4f48548ff48d".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        assert_ok!(result);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        assert_matches!(result, Err(SendEmailError::Transient(_)));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_permanently_if_the_server_returns_400() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        assert_matches!(result, Err(SendEmailError::Permanent(_)));
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        assert_matches!(result, Err(SendEmailError::Transient(_)));
+    }
+
+    #[tokio::test]
+    async fn send_batch_maps_results_back_to_each_email() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "first-id"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"},
+                {"ErrorCode": 405, "Message": "Not allowed to send"},
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut results = email_client.send_batch(&batch(3)).await.into_iter();
+
+        assert_ok_eq!(results.next().unwrap(), Some("first-id".to_string()));
+        assert_matches!(results.next().unwrap(), Err(SendEmailError::Permanent(_)));
+        assert_matches!(results.next().unwrap(), Err(SendEmailError::Transient(_)));
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_batches_larger_than_postmark_accepts() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(AcceptBatch)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_batch(&batch(501)).await;
+
+        assert_eq!(results.len(), 501);
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_emails_the_response_does_not_report_on() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "first-id"},
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut results = email_client.send_batch(&batch(2)).await.into_iter();
+
+        assert_ok_eq!(results.next().unwrap(), Some("first-id".to_string()));
+        assert_matches!(results.next().unwrap(), Err(SendEmailError::Transient(_)));
+        assert!(results.next().is_none());
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_every_email_if_the_response_is_unreadable() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_batch(&batch(2)).await;
+
+        assert_eq!(results.len(), 2);
+        for result in results {
+            assert_matches!(result, Err(SendEmailError::Transient(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_if_the_request_fails() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_batch(&batch(2)).await;
+
+        assert_eq!(results.len(), 2);
+        for result in results {
+            assert_matches!(result, Err(SendEmailError::Transient(_)));
+        }
+    }
+}
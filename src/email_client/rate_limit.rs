@@ -0,0 +1,188 @@
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::{Email, EmailTransport, SendEmailResult};
+use crate::domain::SubscriberEmail;
+
+/// Wraps another transport so that everyone sharing it stays under a global send rate.
+pub struct RateLimitedEmailClient {
+    inner: Arc<dyn EmailTransport>,
+    limiter: RateLimiter,
+}
+
+impl RateLimitedEmailClient {
+    pub fn new(inner: Arc<dyn EmailTransport>, max_emails_per_second: NonZeroU32) -> Self {
+        Self {
+            inner,
+            limiter: RateLimiter::new(max_emails_per_second),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for RateLimitedEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> SendEmailResult {
+        self.limiter.acquire(1).await;
+        self.inner
+            .send_email(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+
+    /// Batches are sent in chunks of at most a second's worth of emails, as transports without a
+    /// batch API send every email of a chunk right away.
+    async fn send_batch(&self, emails: &[Email]) -> Vec<SendEmailResult> {
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(self.limiter.max_per_second.get() as usize) {
+            // Chunks are no larger than a `NonZeroU32`.
+            self.limiter.acquire(chunk.len() as u32).await;
+            results.extend(self.inner.send_batch(chunk).await);
+        }
+        results
+    }
+}
+
+/// Hands out evenly spaced send slots, one per email.
+///
+/// A batch takes as many slots as it has emails, so it goes out at once and the next send waits
+/// for the time the batch used up.
+struct RateLimiter {
+    max_per_second: NonZeroU32,
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(max_per_second: NonZeroU32) -> Self {
+        Self {
+            max_per_second,
+            interval: Duration::from_secs(1) / max_per_second.get(),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn acquire(&self, n: u32) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval.saturating_mul(n);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{RateLimitedEmailClient, RateLimiter};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{Email, EmailTransport, SendEmailResult};
+    use tokio::time::Instant;
+
+    /// Records when each email is sent, one at a time like the backends without a batch API.
+    #[derive(Default)]
+    struct RecordingTransport {
+        sent_at: Mutex<Vec<Instant>>,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailTransport for RecordingTransport {
+        async fn send_email(
+            &self,
+            _recipient: &SubscriberEmail,
+            _subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _headers: &[(&str, &str)],
+        ) -> SendEmailResult {
+            self.sent_at.lock().unwrap().push(Instant::now());
+            Ok(None)
+        }
+    }
+
+    fn batch(n: usize) -> Vec<Email> {
+        (0..n)
+            .map(|_| Email {
+                recipient: SubscriberEmail::parse("ursula@example.com").unwrap(),
+                subject: "Subject".into(),
+                html_content: "<p>Content</p>".into(),
+                text_content: "Content".into(),
+                headers: vec![],
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn first_send_is_not_delayed() {
+        let limiter = RateLimiter::new(NonZeroU32::new(10).unwrap());
+        let start = Instant::now();
+
+        limiter.acquire(1).await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_are_spaced_out_across_tasks() {
+        let limiter = Arc::new(RateLimiter::new(NonZeroU32::new(10).unwrap()));
+        let start = Instant::now();
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move { limiter.acquire(1).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // The tenth send goes out 900ms after the first one.
+        assert_eq!(start.elapsed(), Duration::from_millis(900));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_batch_delays_the_next_send_by_its_size() {
+        let limiter = RateLimiter::new(NonZeroU32::new(10).unwrap());
+        let start = Instant::now();
+
+        limiter.acquire(20).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire(1).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batches_larger_than_the_rate_are_spread_over_several_seconds() {
+        let inner = Arc::new(RecordingTransport::default());
+        let client = RateLimitedEmailClient::new(inner.clone(), NonZeroU32::new(10).unwrap());
+        let start = Instant::now();
+
+        let results = client.send_batch(&batch(25)).await;
+
+        assert_eq!(results.len(), 25);
+        let seconds: Vec<_> = inner
+            .sent_at
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|sent_at| (*sent_at - start).as_secs())
+            .collect();
+        let expected: Vec<u64> = (0..25).map(|i| i / 10).collect();
+        assert_eq!(seconds, expected);
+    }
+}
@@ -0,0 +1,213 @@
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{EmailTransport, SendEmailError, build_message};
+use crate::domain::SubscriberEmail;
+
+/// Sends emails to an SMTP relay.
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailClient {
+    /// Without `require_tls` the connection is made in plain text, which is only acceptable for
+    /// a relay on the same machine or network.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, Secret<String>)>,
+        require_tls: bool,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        let message_id = message
+            .headers()
+            .get_raw("Message-ID")
+            .map(ToString::to_string);
+
+        self.transport.send(message).await?;
+        Ok(message_id)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SendEmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        // Only a 5xx reply tells us the relay will never accept the email. Everything else, like
+        // a 4xx reply or failing to connect, might go away on its own.
+        if e.is_permanent() {
+            Self::Permanent(e.into())
+        } else {
+            Self::Transient(e.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailTransport, SendEmailError, SmtpEmailClient};
+    use claims::{assert_matches, assert_ok, assert_some};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A minimal SMTP server accepting a single connection, which answers `RCPT TO` with
+    /// `rcpt_reply` and records the message it receives.
+    async fn smtp_stand_in(rcpt_reply: &'static str) -> (u16, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(String::new()));
+        let data = received.clone();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 Queued\r\n").await.unwrap();
+                    } else {
+                        let mut data = data.lock().unwrap();
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply = match line.split_whitespace().next().unwrap_or_default() {
+                    "EHLO" | "HELO" => "250 localhost\r\n",
+                    "RCPT" => rcpt_reply,
+                    "DATA" => {
+                        in_data = true;
+                        "354 End data with <CR><LF>.<CR><LF>\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => "250 OK\r\n",
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (port, received)
+    }
+
+    fn email_client(port: u16) -> SmtpEmailClient {
+        SmtpEmailClient::new(
+            "127.0.0.1",
+            port,
+            None,
+            false,
+            SubscriberEmail::parse("sender@example.com").unwrap(),
+            std::time::Duration::from_secs(2),
+        )
+        .unwrap()
+    }
+
+    fn recipient() -> SubscriberEmail {
+        SubscriberEmail::parse("recipient@example.com").unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_the_message_to_the_relay() {
+        let (port, received) = smtp_stand_in("250 OK\r\n").await;
+
+        let result = email_client(port)
+            .send_email(
+                &recipient(),
+                "Subject",
+                "<p>Html content</p>",
+                "Text content",
+                &[("List-Unsubscribe", "<https://example.com>")],
+            )
+            .await;
+
+        let message_id = assert_ok!(result);
+        assert_some!(message_id);
+        let received = received.lock().unwrap();
+        assert!(received.contains("To: recipient@example.com"));
+        assert!(received.contains("Subject: Subject"));
+        assert!(received.contains("List-Unsubscribe: <https://example.com>"));
+        assert!(received.contains("Text content"));
+        assert!(received.contains("<p>Html content</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_permanently_if_the_relay_rejects_the_recipient() {
+        let (port, _) = smtp_stand_in("550 No such user\r\n").await;
+
+        let result = email_client(port)
+            .send_email(&recipient(), "Subject", "html", "text", &[])
+            .await;
+
+        assert_matches!(result, Err(SendEmailError::Permanent(_)));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_transiently_if_the_relay_defers_the_recipient() {
+        let (port, _) = smtp_stand_in("451 Try again later\r\n").await;
+
+        let result = email_client(port)
+            .send_email(&recipient(), "Subject", "html", "text", &[])
+            .await;
+
+        assert_matches!(result, Err(SendEmailError::Transient(_)));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_transiently_if_the_relay_is_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let result = email_client(port)
+            .send_email(&recipient(), "Subject", "html", "text", &[])
+            .await;
+
+        assert_matches!(result, Err(SendEmailError::Transient(_)));
+    }
+}
@@ -1,12 +1,29 @@
 use anyhow::Context;
-use sqlx::{Executor, PgPool, Postgres, Transaction};
-use std::time::Duration;
+use rand::Rng;
+use secrecy::Secret;
+use sqlx::{
+    Executor, PgExecutor, PgPool, Postgres, QueryBuilder, Transaction, postgres::types::PgInterval,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 use tracing::{Span, field::display};
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::{DeliverySettings, Settings},
+    domain::{
+        IssueTemplate, Segment, SubscriberEmail, TemplateValues, TrackingToken, UnsubscribeToken,
+    },
+    email_client::{
+        Email, EmailTransport, RateLimitedEmailClient, SendEmailError, SendEmailResult,
+    },
     startup::get_connection_pool,
+    util::{escape_html, unescape_html},
 };
 
 pub enum TaskOutcome {
@@ -14,103 +31,610 @@ pub enum TaskOutcome {
     QueueEmpty,
 }
 
-pub async fn run_workers_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+pub async fn run_workers_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
     let pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
-    worker_loop(pool, email_client).await
+    let delivery_settings = configuration.delivery;
+    let mut email_client = configuration.email_client.client();
+    if let Some(max_emails_per_second) = delivery_settings.max_emails_per_second {
+        email_client = Arc::new(RateLimitedEmailClient::new(
+            email_client,
+            max_emails_per_second,
+        ));
+    }
+
+    let mut workers = JoinSet::new();
+    for _ in 0..delivery_settings.n_workers.max(1) {
+        workers.spawn(worker_loop(
+            pool.clone(),
+            email_client.clone(),
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+            delivery_settings.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    // Workers only stop on their own if something went badly wrong, so bring the rest down too.
+    let outcome = tokio::select! {
+        Some(outcome) = workers.join_next() => {
+            shutdown.cancel();
+            outcome.map_err(anyhow::Error::from).and_then(|o| o)
+        }
+        _ = shutdown.cancelled() => Ok(()),
+    };
+
+    drain_workers(&pool, workers, configuration.application.shutdown_deadline).await;
+    outcome
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(TaskOutcome::QueueEmpty) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
-            }
-            Ok(TaskOutcome::TaskComplete) => {}
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+/// Give workers until `deadline` to finish the batch they are delivering, then abort the rest.
+///
+/// Aborted batches are rolled back and delivered again after a restart.
+async fn drain_workers(
+    pool: &PgPool,
+    mut workers: JoinSet<Result<(), anyhow::Error>>,
+    deadline: Duration,
+) {
+    let n_workers = workers.len();
+    let mut n_drained = 0;
+    let _ = tokio::time::timeout(deadline, async {
+        while let Some(outcome) = workers.join_next().await {
+            match outcome {
+                Ok(Ok(())) => n_drained += 1,
+                Ok(Err(e)) => {
+                    tracing::error!(error.cause_chain = ?e, error.message = %e, "Delivery worker failed");
+                }
+                Err(e) => {
+                    tracing::error!(error.cause_chain = ?e, error.message = %e, "Delivery worker panicked");
+                }
             }
         }
+    })
+    .await;
+    let n_aborted = workers.len();
+    workers.shutdown().await;
+
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(pool)
+        .await
+        .map(|r| r.count)
+        .inspect_err(
+            |e| tracing::warn!(error.cause_chain = ?e, "Failed to count queued deliveries"),
+        )
+        .ok();
+    tracing::info!(
+        n_workers,
+        n_drained,
+        n_aborted,
+        n_queued,
+        "Delivery workers have shut down"
+    );
+}
+
+/// Deliver queued issues until `shutdown` is cancelled.
+///
+/// Cancellation only interrupts the wait between batches: a batch being delivered is always
+/// completed, so that sent emails are never left in the queue.
+pub async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailTransport>,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    delivery_settings: DeliverySettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        let backoff = match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &base_url,
+            &hmac_secret,
+            &delivery_settings,
+        )
+        .await
+        {
+            Ok(TaskOutcome::QueueEmpty) => delivery_settings.idle_backoff(),
+            Ok(TaskOutcome::TaskComplete) => continue,
+            Err(_) => delivery_settings.error_backoff(),
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(backoff) => {}
+        }
     }
+    Ok(())
 }
 
-#[tracing::instrument(name="Try execute task", skip_all, fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty), err)]
+#[tracing::instrument(name="Try execute task", skip_all, fields(newsletter_issue_id=tracing::field::Empty, n_tasks=tracing::field::Empty), err)]
 pub async fn try_execute_task(
     pool: &PgPool,
-    email_client: &EmailClient,
+    email_client: &dyn EmailTransport,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    delivery_settings: &DeliverySettings,
 ) -> Result<TaskOutcome, anyhow::Error> {
-    if let Some((tx, issue_id, email)) = dequeue_task(pool).await? {
-        Span::current()
-            .record("newsletter_issue_id", display(issue_id))
-            .record("subscriber_email", display(&email));
-
-        match SubscriberEmail::parse(email.clone()) {
-            Ok(email) => {
-                let issue = get_issue(pool, issue_id).await?;
-                if let Err(e) = email_client
-                    .send_email(
-                        &email,
-                        &issue.title,
-                        &issue.html_content,
-                        &issue.text_content,
-                    )
-                    .await
-                    .with_context(|| "Failed to send newsletter issue to confirmed subscriber")
-                {
-                    tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to subscriber. \
-                        Skipping...
-                        "
-                    )
+    let Some((mut tx, issue_id, tasks)) = dequeue_tasks(pool, delivery_settings.batch_size).await?
+    else {
+        return Ok(TaskOutcome::QueueEmpty);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("n_tasks", tasks.len());
+
+    let issue = get_issue(pool, issue_id).await?;
+    let recipients = get_confirmed_recipients(pool, issue_id, &tasks).await?;
+    let suppressed = get_suppressed_emails(pool, &tasks).await?;
+
+    let mut pending = Vec::with_capacity(tasks.len());
+    let mut emails = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        if suppressed.contains(&task.subscriber_email.to_lowercase()) {
+            tracing::info!(
+                subscriber_email = %task.subscriber_email,
+                "Skipping a suppressed address"
+            );
+            log_delivery(
+                &mut tx,
+                issue_id,
+                &task.subscriber_email,
+                "skipped_suppressed",
+                false,
+                None,
+            )
+            .await?;
+            delete_task(&mut tx, issue_id, &task.subscriber_email).await?;
+            continue;
+        }
+        let Some(recipient) = recipients.get(&task.subscriber_email) else {
+            tracing::info!(
+                subscriber_email = %task.subscriber_email,
+                "Skipping a subscriber who is no longer confirmed"
+            );
+            log_delivery(
+                &mut tx,
+                issue_id,
+                &task.subscriber_email,
+                "skipped_unsubscribed",
+                false,
+                None,
+            )
+            .await?;
+            delete_task(&mut tx, issue_id, &task.subscriber_email).await?;
+            continue;
+        };
+
+        let subscriber_email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(subscriber_email) => subscriber_email,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber\
+                    Their stored contact information is invalid");
+                log_delivery(
+                    &mut tx,
+                    issue_id,
+                    &task.subscriber_email,
+                    "skipped_invalid_email",
+                    false,
+                    None,
+                )
+                .await?;
+                delete_task(&mut tx, issue_id, &task.subscriber_email).await?;
+                continue;
+            }
+        };
+
+        emails.push(personalize_issue(
+            &issue,
+            issue_id,
+            subscriber_email,
+            recipient,
+            base_url,
+            hmac_secret,
+            delivery_settings.tracking_enabled,
+        ));
+        pending.push(task);
+    }
+
+    let results = if emails.is_empty() {
+        Vec::new()
+    } else {
+        email_client.send_batch(&emails).await
+    };
+    if results.len() != pending.len() {
+        tracing::error!(
+            n_emails = pending.len(),
+            n_results = results.len(),
+            "The email transport did not report on every email of the batch"
+        );
+    }
+    // Emails without a result may or may not have gone out, so they are retried rather than
+    // risk them never being delivered.
+    let mut results = results.into_iter();
+    for task in &pending {
+        let result = results.next().unwrap_or_else(|| {
+            Err(SendEmailError::Transient(
+                "The email transport did not report on this email".into(),
+            ))
+        });
+        record_result(&mut tx, issue_id, task, result, delivery_settings).await?;
+    }
+    tx.commit().await?;
+    Ok(TaskOutcome::TaskComplete)
+}
+
+/// Build the email for a single subscriber, with links only they can use.
+///
+/// If the issue tracks clicks its links go through a redirect recording them, and if it tracks
+/// opens a pixel recording them is added, unless tracking is disabled altogether.
+fn personalize_issue(
+    issue: &NewsletterIssue,
+    issue_id: Uuid,
+    recipient: SubscriberEmail,
+    subscriber: &Recipient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    tracking_enabled: bool,
+) -> Email {
+    let unsubscribe_page = format!("{base_url}/subscription/unsubscribe");
+    let unsubscribe_link = format!(
+        "{unsubscribe_page}?token={}",
+        UnsubscribeToken::generate(subscriber.id, hmac_secret).as_ref()
+    );
+    let view_in_browser_link = format!("{base_url}/issues/{issue_id}");
+    let values = TemplateValues {
+        name: Some(&subscriber.name),
+        email: Some(recipient.as_ref()),
+        unsubscribe_url: Some(&unsubscribe_link),
+        view_in_browser_url: Some(&view_in_browser_link),
+    };
+    let mut html_content = render_content(&issue.html_content, |t| t.render_html(&values));
+    if tracking_enabled && issue.track_clicks {
+        html_content = track_links(&html_content, |url| {
+            // Leaving is not the kind of engagement clicks are tracked for.
+            (!url.starts_with(&unsubscribe_page)).then(|| {
+                let token = TrackingToken::click(issue_id, subscriber.id, url, hmac_secret);
+                format!("{base_url}/t/c/{}", token.as_ref())
+            })
+        });
+    }
+    let tracking_pixel = (tracking_enabled && issue.track_opens).then(|| {
+        let token = TrackingToken::open(issue_id, subscriber.id, hmac_secret);
+        format!(
+            r#"<img src="{base_url}/t/o/{}" width="1" height="1" alt="">"#,
+            token.as_ref()
+        )
+    });
+    Email {
+        subject: issue.title.clone(),
+        html_content: add_html_footer(
+            &html_content,
+            &view_in_browser_link,
+            &unsubscribe_link,
+            tracking_pixel.as_deref(),
+        ),
+        text_content: add_text_footer(
+            &render_content(&issue.text_content, |t| t.render_text(&values)),
+            &view_in_browser_link,
+            &unsubscribe_link,
+        ),
+        recipient,
+        headers: vec![
+            (
+                "List-Unsubscribe".to_string(),
+                format!("<{unsubscribe_link}>"),
+            ),
+            (
+                "List-Unsubscribe-Post".to_string(),
+                "List-Unsubscribe=One-Click".to_string(),
+            ),
+        ],
+    }
+}
+
+/// Render `content` as a template, or leave it as is if it is not a valid one.
+///
+/// Templates are validated before an issue is published, so only issues published before
+/// variables existed can fail here, and their braces were meant literally.
+pub fn render_content(content: &str, render: impl FnOnce(&IssueTemplate) -> String) -> String {
+    match IssueTemplate::parse(content) {
+        Ok(template) => render(&template),
+        Err(_) => content.to_owned(),
+    }
+}
+
+/// Replace the url of every http(s) link in `html` by what `track` returns for it, if anything.
+///
+/// Only quoted `href` attributes of `<a>` tags are recognized, which is what editors produce.
+fn track_links(html: &str, mut track: impl FnMut(&str) -> Option<String>) -> String {
+    // Lowercasing ascii keeps byte offsets, so positions found in one apply to the other.
+    let lowercase = html.to_ascii_lowercase();
+    let mut tracked = String::with_capacity(html.len());
+    let mut position = 0;
+    while let Some(i) = lowercase[position..].find("<a") {
+        let tag_start = position + i;
+        let tag_end = lowercase[tag_start..]
+            .find('>')
+            .map_or(html.len(), |i| tag_start + i);
+        tracked.push_str(&html[position..tag_start]);
+        position = tag_end;
+
+        let tag = &html[tag_start..tag_end];
+        let is_link = tag[2..].starts_with(|c: char| c.is_ascii_whitespace());
+        let replacement = href_range(&lowercase[tag_start..tag_end])
+            .filter(|_| is_link)
+            .and_then(|range| {
+                let url = unescape_html(&tag[range.clone()]);
+                let lowercase_url = url.to_ascii_lowercase();
+                if !lowercase_url.starts_with("http://") && !lowercase_url.starts_with("https://") {
+                    return None;
                 }
+                track(&url).map(|tracked_url| (range, tracked_url))
+            });
+        match replacement {
+            Some((range, tracked_url)) => {
+                tracked.push_str(&tag[..range.start]);
+                tracked.push_str(&escape_html(&tracked_url));
+                tracked.push_str(&tag[range.end..]);
             }
-            Err(e) => tracing::warn!(error.cause_chain = ?e,
-                "Skipping a confirmed subscriber\
-                Their stored contact information is invalid"),
+            None => tracked.push_str(tag),
         }
-        delete_task(tx, issue_id, &email).await?;
-        return Ok(TaskOutcome::TaskComplete);
     }
+    tracked.push_str(&html[position..]);
+    tracked
+}
 
-    Ok(TaskOutcome::QueueEmpty)
+/// Where the value of the quoted `href` attribute of a lowercase tag is.
+fn href_range(tag: &str) -> Option<std::ops::Range<usize>> {
+    let mut from = 0;
+    while let Some(i) = tag[from..].find("href") {
+        let name_start = from + i;
+        from = name_start + "href".len();
+        if !tag[..name_start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(value) = tag[from..].trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let start = tag.len() - value.len() + 1;
+        let end = start + tag[start..].find(quote)?;
+        return Some(start..end);
+    }
+    None
 }
 
-#[tracing::instrument(skip_all, name = "Dequeue task")]
-async fn dequeue_task(
-    pool: &PgPool,
-) -> Result<Option<(Transaction<'_, Postgres>, Uuid, String)>, anyhow::Error> {
-    let mut transaction = pool.begin().await?;
+/// Complete, retry or dead-letter a task depending on whether its email was sent.
+async fn record_result(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    task: &Task,
+    result: SendEmailResult,
+    delivery_settings: &DeliverySettings,
+) -> Result<(), anyhow::Error> {
+    let email = &task.subscriber_email;
+    match result {
+        Ok(message_id) => {
+            log_delivery(tx, issue_id, email, "sent", true, message_id.as_deref()).await?;
+            delete_task(tx, issue_id, email).await?;
+        }
+        Err(e @ SendEmailError::Transient(_))
+            if i32::from(task.n_retries) + 1 < i32::from(delivery_settings.max_attempts) =>
+        {
+            let delay = retry_delay(task.n_retries, delivery_settings);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %email,
+                "Failed to deliver issue to subscriber. Retrying in {delay:?}"
+            );
+            log_delivery(tx, issue_id, email, "queued", true, None).await?;
+            retry_task(tx, issue_id, email, delay).await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %email,
+                "Failed to deliver issue to subscriber. Giving up"
+            );
+            log_delivery(tx, issue_id, email, "failed", true, None).await?;
+            fail_task(tx, issue_id, email, task.n_retries, e).await?;
+        }
+    }
+    Ok(())
+}
 
-    let res = sqlx::query!(
+/// Queue delivery of an issue to every confirmed subscriber of at least one of its lists who
+/// is in its segment.
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction))]
+pub async fn enque_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let issue = sqlx::query!(
+        "SELECT segment FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    // Segments are validated before an issue is stored, so this only fails if the syntax changes.
+    let segment = issue
+        .segment
+        .as_deref()
+        .map(Segment::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)
+        .context("Failed to parse the segment of the issue")?;
+    let list_ids = sqlx::query!(
+        "SELECT list_id FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.list_id)
+    .collect();
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue(newsletter_issue_id, subscriber_email) SELECT ",
+    );
+    query.push_bind(newsletter_issue_id);
+    query.push(", email FROM (");
+    push_recipients(&mut query, list_ids, segment.as_ref());
+    query.push(") recipients");
+    query.build().execute(&mut **transaction).await?;
+
+    let query = sqlx::query!(
         r#"
-        SELECT 
+        INSERT INTO issue_deliveries(
             newsletter_issue_id,
-            subscriber_email
+            subscriber_email,
+            status,
+            queued_at,
+            updated_at
+            )
+            SELECT newsletter_issue_id, subscriber_email, 'queued', now(), now()
+            FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Count the subscribers an issue sent to `list_ids` and `segment` would be delivered to.
+pub async fn count_recipients(
+    executor: impl PgExecutor<'_>,
+    list_ids: Vec<Uuid>,
+    segment: Option<&Segment>,
+) -> Result<i64, anyhow::Error> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM (");
+    push_recipients(&mut query, list_ids, segment);
+    query.push(") recipients");
+    let count = query
+        .build_query_scalar()
+        .fetch_one(executor)
+        .await
+        .context("Failed to count recipients")?;
+    Ok(count)
+}
+
+/// Push a query for the email of every confirmed subscriber of one of the lists who is in the
+/// segment and whose address is not suppressed.
+fn push_recipients(
+    query: &mut QueryBuilder<'_, Postgres>,
+    list_ids: Vec<Uuid>,
+    segment: Option<&Segment>,
+) {
+    query.push(
+        r#"
+        SELECT DISTINCT s.email
+        FROM subscriptions s
+        JOIN list_subscriptions l ON l.subscriber_id = s.id
+        WHERE
+            s.status = 'confirmed'
+            AND l.status = 'confirmed'
+            AND NOT EXISTS (SELECT 1 FROM suppressions x WHERE x.email = lower(s.email))
+            AND l.list_id = ANY("#,
+    );
+    query.push_bind(list_ids);
+    query.push(")");
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_sql(query);
+    }
+}
+
+/// Drop every delivery still queued for a subscriber who should no longer receive issues.
+#[tracing::instrument(name = "Cancel queued deliveries", skip(transaction))]
+pub async fn cancel_queued_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_email: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries d
+        SET status = 'skipped_unsubscribed', updated_at = now()
+        FROM issue_delivery_queue q
+        WHERE
+            q.newsletter_issue_id = d.newsletter_issue_id
+            AND q.subscriber_email = d.subscriber_email
+            AND d.subscriber_email = $1
+        "#,
+        subscriber_email
+    );
+    transaction.execute(query).await?;
+
+    let query = sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+        subscriber_email
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// A queued delivery of an issue to one subscriber.
+struct Task {
+    subscriber_email: String,
+    /// How many times delivery has been retried so far.
+    n_retries: i16,
+}
+
+/// Claimed tasks along with the transaction holding their row locks and the issue they are for.
+type Batch<'a> = (Transaction<'a, Postgres>, Uuid, Vec<Task>);
+
+/// Claim up to `batch_size` due tasks, all for the same issue so they can be sent together.
+#[tracing::instrument(skip_all, name = "Dequeue tasks")]
+async fn dequeue_tasks(pool: &PgPool, batch_size: u16) -> Result<Option<Batch<'_>>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let Some(first) = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
         FROM issue_delivery_queue
+        WHERE execute_after <= now()
         FOR UPDATE
         SKIP LOCKED
         LIMIT 1
         "#,
     )
     .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    // The row locked above is ours, so it is not skipped here.
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1
+            AND execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $2
+        "#,
+        first.newsletter_issue_id,
+        i64::from(batch_size.max(1))
+    )
+    .fetch_all(&mut *transaction)
     .await?;
 
-    if let Some(item) = res {
-        Ok(Some((
-            transaction,
-            item.newsletter_issue_id,
-            item.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok(Some((transaction, first.newsletter_issue_id, tasks)))
 }
 
 #[tracing::instrument(skip_all, name = "Delete task")]
 async fn delete_task(
-    mut tx: Transaction<'_, Postgres>,
+    tx: &mut Transaction<'_, Postgres>,
     id: Uuid,
     email: &str,
 ) -> Result<(), anyhow::Error> {
@@ -125,15 +649,211 @@ async fn delete_task(
     );
 
     tx.execute(query).await?;
-    tx.commit().await?;
     Ok(())
 }
 
+/// Record the outcome of handling a task in the persistent delivery log.
+#[tracing::instrument(skip_all, name = "Log delivery", fields(status=%status))]
+async fn log_delivery(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    email: &str,
+    status: &str,
+    attempted: bool,
+    provider_message_id: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET
+            status = $3,
+            n_attempts = n_attempts + $4,
+            provider_message_id = $5,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1
+            AND subscriber_email = $2"#,
+        id,
+        email,
+        status,
+        i16::from(attempted),
+        provider_message_id
+    );
+
+    tx.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, name = "Retry task")]
+async fn retry_task(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    email: &str,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let delay: PgInterval = delay.try_into().map_err(|e| anyhow::anyhow!("{e}"))?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + $3
+        WHERE
+            newsletter_issue_id = $1
+            AND subscriber_email = $2"#,
+        id,
+        email,
+        delay
+    );
+
+    tx.execute(query).await?;
+    Ok(())
+}
+
+/// Move a task that can no longer be delivered to the dead-letter table.
+#[tracing::instrument(skip_all, name = "Fail task")]
+async fn fail_task(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    email: &str,
+    n_retries: i16,
+    error: SendEmailError,
+) -> Result<(), anyhow::Error> {
+    let error = anyhow::Error::from(error);
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO failed_deliveries(
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        id,
+        email,
+        n_retries,
+        format!("{error:#}")
+    );
+
+    tx.execute(query).await?;
+    delete_task(tx, id, email).await
+}
+
+/// Exponential backoff with jitter, so that subscribers who failed during the same provider outage
+/// are not all retried at the same moment.
+fn retry_delay(n_retries: i16, delivery_settings: &DeliverySettings) -> Duration {
+    let exponent = u32::try_from(n_retries).unwrap_or(0).min(16);
+    let delay = delivery_settings
+        .retry_base_delay()
+        .saturating_mul(2u32.pow(exponent))
+        .min(delivery_settings.retry_max_delay());
+    // Always wait at least half of the delay. Postgres intervals only go down to microseconds so
+    // stick to whole milliseconds.
+    let half = u64::try_from(delay.as_millis() / 2).unwrap_or(u64::MAX);
+    Duration::from_millis(half + rand::thread_rng().gen_range(0..=half))
+}
+
+struct Recipient {
+    id: Uuid,
+    name: String,
+}
+
+/// Map the email of every task's subscriber who is still confirmed on one of the issue's lists
+/// to the details their email is personalized with.
+#[tracing::instrument(skip_all, name = "Get confirmed recipients")]
+async fn get_confirmed_recipients(
+    pool: &PgPool,
+    issue_id: Uuid,
+    tasks: &[Task],
+) -> Result<HashMap<String, Recipient>, anyhow::Error> {
+    let emails: Vec<_> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    // Subscribers may have unsubscribed since the issue was published.
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT s.email, s.id, s.name
+        FROM subscriptions s
+        JOIN list_subscriptions l ON l.subscriber_id = s.id
+        JOIN newsletter_issue_lists i ON i.list_id = l.list_id
+        WHERE
+            s.email = ANY($1)
+            AND i.newsletter_issue_id = $2
+            AND s.status = 'confirmed'
+            AND l.status = 'confirmed'
+        "#,
+        &emails,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let recipient = Recipient {
+                id: r.id,
+                name: r.name,
+            };
+            (r.email, recipient)
+        })
+        .collect())
+}
+
+/// The lowercase emails of the tasks whose address has bounced or complained since they were
+/// queued.
+#[tracing::instrument(skip_all, name = "Get suppressed emails")]
+async fn get_suppressed_emails(
+    pool: &PgPool,
+    tasks: &[Task],
+) -> Result<HashSet<String>, anyhow::Error> {
+    let emails: Vec<_> = tasks
+        .iter()
+        .map(|t| t.subscriber_email.to_lowercase())
+        .collect();
+    let rows = sqlx::query!(
+        "SELECT email FROM suppressions WHERE email = ANY($1)",
+        &emails
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.email).collect())
+}
+
+fn add_html_footer(
+    html_content: &str,
+    view_in_browser_link: &str,
+    unsubscribe_link: &str,
+    tracking_pixel: Option<&str>,
+) -> String {
+    let footer = format!(
+        "<p><a href=\"{view_in_browser_link}\">View this email in your browser</a></p>\
+        <p>Don't want to receive these emails anymore? \
+        <a href=\"{unsubscribe_link}\">Unsubscribe</a></p>{}",
+        tracking_pixel.unwrap_or_default()
+    );
+    // Keep the document well formed if the issue is a full html document.
+    match html_content.rfind("</body>") {
+        Some(i) => format!("{}{footer}{}", &html_content[..i], &html_content[i..]),
+        None => format!("{html_content}{footer}"),
+    }
+}
+
+fn add_text_footer(
+    text_content: &str,
+    view_in_browser_link: &str,
+    unsubscribe_link: &str,
+) -> String {
+    format!(
+        "{text_content}\n\n--\nView this email in your browser: {view_in_browser_link}\n\
+        Don't want to receive these emails anymore? Unsubscribe: {unsubscribe_link}"
+    )
+}
+
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
     Ok(sqlx::query_as!(
         NewsletterIssue,
         r#"
-            SELECT title,html_content,text_content 
+            SELECT title, html_content, text_content, track_opens, track_clicks
             FROM newsletter_issues 
             WHERE
                 newsletter_issue_id = $1
@@ -148,4 +868,67 @@ struct NewsletterIssue {
     title: String,
     html_content: String,
     text_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, track_links};
+    use crate::configuration::DeliverySettings;
+    use std::time::Duration;
+
+    fn settings() -> DeliverySettings {
+        DeliverySettings {
+            n_workers: 1,
+            batch_size: 100,
+            max_emails_per_second: None,
+            idle_backoff_milliseconds: 10_000,
+            error_backoff_milliseconds: 1000,
+            max_attempts: 5,
+            retry_base_delay_milliseconds: 1000,
+            retry_max_delay_milliseconds: 10_000,
+            tracking_enabled: true,
+        }
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially() {
+        for n_retries in 0..3 {
+            let expected = Duration::from_secs(1 << n_retries);
+            let delay = retry_delay(n_retries, &settings());
+            assert!(delay >= expected / 2 && delay <= expected, "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        let delay = retry_delay(i16::MAX, &settings());
+        assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn http_links_are_tracked() {
+        let html = r#"<p><A class="button" HREF="https://example.com/?a=1&amp;b=2">Read</A>
+            <a href='http://example.com'>Home</a> <abbr href="https://example.com">x</abbr>
+            <a href="mailto:ursula@example.com">Write</a> <a data-href="https://example.com">
+            <a name="top">Top</a></p>"#;
+
+        let tracked = track_links(html, |url| Some(format!("https://t.example.com/{url}")));
+
+        assert_eq!(
+            tracked,
+            r#"<p><A class="button" HREF="https://t.example.com/https://example.com/?a=1&amp;b=2">Read</A>
+            <a href='https://t.example.com/http://example.com'>Home</a> <abbr href="https://example.com">x</abbr>
+            <a href="mailto:ursula@example.com">Write</a> <a data-href="https://example.com">
+            <a name="top">Top</a></p>"#
+        );
+    }
+
+    #[test]
+    fn links_can_be_left_untracked() {
+        let html =
+            r#"<a href="https://example.com/unsubscribe">Leave</a><a href="https://example.com"#;
+        assert_eq!(track_links(html, |_| None), html);
+    }
 }
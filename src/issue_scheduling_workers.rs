@@ -0,0 +1,69 @@
+use sqlx::{Executor, PgPool};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    configuration::Settings, issue_delivery_workers::enque_delivery_tasks,
+    startup::get_connection_pool,
+};
+
+pub async fn run_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    while !shutdown.is_cancelled() {
+        if let Err(e) = publish_due_issues(&pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to publish scheduled newsletter issues"
+            );
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(Duration::from_secs(10)) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Publish every scheduled issue whose time has come and queue it for delivery.
+///
+/// Returns how many issues were published.
+#[tracing::instrument(name = "Publish due issues", skip_all)]
+pub async fn publish_due_issues(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_for <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    for issue in &issues {
+        let query = sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = 'published', published_at = now()
+            WHERE newsletter_issue_id = $1
+            "#,
+            issue.newsletter_issue_id
+        );
+        transaction.execute(query).await?;
+        enque_delivery_tasks(&mut transaction, issue.newsletter_issue_id).await?;
+        tracing::info!(
+            newsletter_issue_id = %issue.newsletter_issue_id,
+            "Published scheduled newsletter issue"
+        );
+    }
+
+    transaction.commit().await?;
+    Ok(issues.len())
+}
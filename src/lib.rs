@@ -1,12 +1,17 @@
 pub mod authentication;
+pub mod clock;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
 pub mod idempotency;
 pub mod issue_delivery_workers;
+pub mod issue_scheduling_workers;
+pub mod lists;
 pub mod routes;
 pub mod session_state;
+pub mod shutdown;
 pub mod startup;
+pub mod subscription_expiry_workers;
 pub mod telemetry;
 pub mod util;
 
@@ -14,4 +19,6 @@ pub mod util;
 pub struct Subscription {
     name: String,
     email: String,
+    /// The list to subscribe to. The default list when missing.
+    list_id: Option<uuid::Uuid>,
 }
@@ -0,0 +1,114 @@
+use std::fmt::Write;
+
+use anyhow::Context;
+use sqlx::{PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::util::escape_html;
+
+/// One of the newsletters people can subscribe to.
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub name: String,
+}
+
+pub async fn get_lists(executor: impl PgExecutor<'_>) -> Result<Vec<MailingList>, anyhow::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, name FROM lists ORDER BY created_at, list_id"
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to fetch lists")
+}
+
+pub async fn get_list(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+) -> Result<Option<MailingList>, anyhow::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, name FROM lists WHERE list_id = $1",
+        list_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch list")
+}
+
+/// The list used when none is picked: the oldest one, which everybody was subscribed to before
+/// there could be several.
+pub async fn get_default_list(executor: impl PgExecutor<'_>) -> Result<MailingList, anyhow::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, name FROM lists ORDER BY created_at, list_id LIMIT 1"
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to fetch the default list")
+}
+
+/// Target an issue at the given lists, or the default list if there are none.
+///
+/// Returns `Ok(false)` without changing anything if one of the lists does not exist.
+#[tracing::instrument(name = "Set issue lists", skip(transaction))]
+pub async fn set_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<bool, anyhow::Error> {
+    let list_ids = if list_ids.is_empty() {
+        vec![get_default_list(&mut **transaction).await?.list_id]
+    } else {
+        list_ids.to_vec()
+    };
+
+    let n_lists = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists(newsletter_issue_id, list_id)
+        SELECT $1, list_id FROM lists WHERE list_id = ANY($2)
+        "#,
+        newsletter_issue_id,
+        &list_ids
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store issue lists")?
+    .rows_affected();
+
+    let mut unique = list_ids;
+    unique.sort();
+    unique.dedup();
+    Ok(n_lists == unique.len() as u64)
+}
+
+/// Checkboxes to pick lists in a form, with the default list checked.
+pub fn list_checkboxes(lists: &[MailingList]) -> String {
+    let mut html = String::new();
+    for (i, list) in lists.iter().enumerate() {
+        let checked = if i == 0 { " checked" } else { "" };
+        write!(
+            &mut html,
+            r#"<label><input name="list_id" type="checkbox" value="{}"{checked}> {}</label><br>"#,
+            list.list_id,
+            escape_html(&list.name)
+        )
+        .unwrap();
+    }
+    html
+}
+
+/// Options for a `<select>` picking one list, the default list first.
+pub fn list_options(lists: &[MailingList]) -> String {
+    let mut html = String::new();
+    for list in lists {
+        write!(
+            &mut html,
+            r#"<option value="{}">{}</option>"#,
+            list.list_id,
+            escape_html(&list.name)
+        )
+        .unwrap();
+    }
+    html
+}
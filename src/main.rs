@@ -1,10 +1,14 @@
 use std::fmt::Debug;
 use std::fmt::Display;
 
-use tokio::task::JoinError;
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
 use zero2prod::idempotency;
 use zero2prod::issue_delivery_workers::run_workers_until_stopped;
+use zero2prod::issue_scheduling_workers;
+use zero2prod::shutdown::cancel_on_signal;
 use zero2prod::startup::Application;
+use zero2prod::subscription_expiry_workers;
 use zero2prod::{configuration::get_configuration, telemetry::*};
 
 #[actix_web::main]
@@ -13,23 +17,58 @@ async fn main() -> Result<(), anyhow::Error> {
     init_subscriber(subscriber);
 
     let configuration = get_configuration().expect("Failed to read configuration.");
-    let email_worker = run_workers_until_stopped(configuration.clone());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
+    let email_worker = run_workers_until_stopped(configuration.clone(), shutdown.clone());
     let idempotency_cleanup_worker =
-        idempotency::expiry_workers::run_until_stopped(configuration.clone());
-    let app = Application::build(configuration).await?.run_until_stopped();
+        idempotency::expiry_workers::run_until_stopped(configuration.clone(), shutdown.clone());
+    let scheduling_worker =
+        issue_scheduling_workers::run_until_stopped(configuration.clone(), shutdown.clone());
+    let subscription_cleanup_worker =
+        subscription_expiry_workers::run_until_stopped(configuration.clone(), shutdown.clone());
+    let app = Application::build(configuration)
+        .await?
+        .run_until_stopped(shutdown.clone());
     let app = tokio::spawn(app);
     let email_worker = tokio::spawn(email_worker);
     let idempotency_cleanup_worker = tokio::spawn(idempotency_cleanup_worker);
+    let scheduling_worker = tokio::spawn(scheduling_worker);
+    let subscription_cleanup_worker = tokio::spawn(subscription_cleanup_worker);
 
-    tokio::select! {
-        o = app => report_exit("API", o),
-        o = email_worker => report_exit("Email Background worker", o),
-        o = idempotency_cleanup_worker => report_exit("Idempotency Cleanup Background Worker", o)
-    }
+    tokio::join!(
+        run_to_completion("API", app, &shutdown),
+        run_to_completion("Email Background worker", email_worker, &shutdown),
+        run_to_completion(
+            "Idempotency Cleanup Background Worker",
+            idempotency_cleanup_worker,
+            &shutdown
+        ),
+        run_to_completion(
+            "Newsletter Scheduling Background Worker",
+            scheduling_worker,
+            &shutdown
+        ),
+        run_to_completion(
+            "Subscription Cleanup Background Worker",
+            subscription_cleanup_worker,
+            &shutdown
+        ),
+    );
 
     Ok(())
 }
 
+/// Wait for a task to exit and make every other task shut down with it.
+async fn run_to_completion<E: Debug + Display>(
+    task_name: &str,
+    task: JoinHandle<Result<(), E>>,
+    shutdown: &CancellationToken,
+) {
+    report_exit(task_name, task.await);
+    shutdown.cancel();
+}
+
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
     match outcome {
         Ok(Ok(())) => tracing::info!("{task_name} has exited"),
@@ -1,21 +1,34 @@
+use std::fmt::Write;
+
 use actix_web::{HttpResponse, http::header::ContentType};
+use actix_web_flash_messages::IncomingFlashMessages;
 
 use sqlx::PgPool;
 
 use crate::{
-    authentication::UserId,
-    util::{e500, get_username},
+    authentication::CurrentUser,
+    util::{e500, escape_html, get_username},
 };
 
 pub async fn admin_dashboard(
+    received: IncomingFlashMessages,
     db_pool: actix_web::web::Data<PgPool>,
-    user_id: actix_web::web::ReqData<UserId>,
+    user: actix_web::web::ReqData<CurrentUser>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = user_id.into_inner();
-    let username = get_username(db_pool.as_ref(), *user_id)
+    let user = user.into_inner();
+    let username = get_username(db_pool.as_ref(), *user.user_id)
         .await
         .map_err(e500)?;
+    let mut messages = String::new();
+    for msg in received.iter() {
+        write!(&mut messages, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(format!(include_str!("dashboard.html"), username)))
+        .body(format!(
+            include_str!("dashboard.html"),
+            messages = messages,
+            username = escape_html(&username),
+            role = user.role
+        )))
 }
@@ -0,0 +1,34 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::UserId,
+    util::{e500, escape_html, get_user_email},
+};
+
+pub async fn change_email_form(
+    received: IncomingFlashMessages,
+    db_pool: actix_web::web::Data<PgPool>,
+    user_id: actix_web::web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+    let email = get_user_email(db_pool.as_ref(), **user_id)
+        .await
+        .map_err(e500)?
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("email_form.html"),
+            messages,
+            escape_html(&email)
+        )))
+}
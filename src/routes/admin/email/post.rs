@@ -0,0 +1,48 @@
+use actix_web::{HttpResponse, web::Form};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::util::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Change email", skip(form, db_pool, user_id))]
+pub async fn change_email(
+    form: Form<FormData>,
+    db_pool: actix_web::web::Data<PgPool>,
+    user_id: actix_web::web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(_) => {
+            FlashMessage::error("The email address you entered is invalid.").send();
+            return Ok(see_other("/admin/email"));
+        }
+    };
+
+    let result = sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        email.as_ref(),
+        **user_id
+    )
+    .execute(db_pool.as_ref())
+    .await;
+
+    match result {
+        Ok(_) => FlashMessage::info("Your email address has been changed.").send(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            FlashMessage::error("This email address is already used by another user.").send()
+        }
+        Err(e) => {
+            return Err(e500(
+                anyhow::Error::from(e).context("Failed to change user's email in the database"),
+            ));
+        }
+    }
+    Ok(see_other("/admin/email"))
+}
@@ -0,0 +1,50 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html};
+
+pub async fn get_lists(
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.name, COUNT(s.subscriber_id) AS "n_confirmed!"
+        FROM lists l
+        LEFT JOIN list_subscriptions s ON s.list_id = l.list_id AND s.status = 'confirmed'
+        GROUP BY l.list_id
+        ORDER BY l.created_at, l.list_id
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch lists")
+    .map_err(e500)?;
+
+    let mut rows = String::new();
+    for list in lists {
+        write!(
+            &mut rows,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape_html(&list.name),
+            list.n_confirmed
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("lists.html"), messages, rows)))
+}
@@ -0,0 +1,48 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+}
+
+#[tracing::instrument(name = "Create list", skip(form, pool, _user_id), fields(name = %form.name))]
+pub async fn create_list(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.0.name.trim().to_string();
+    if name.is_empty() {
+        FlashMessage::error("The list needs a name.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+
+    let result = sqlx::query!(
+        "INSERT INTO lists(list_id, name) VALUES ($1, $2)",
+        Uuid::new_v4(),
+        name
+    )
+    .execute(pool.as_ref())
+    .await;
+
+    match result {
+        Ok(_) => {
+            FlashMessage::info(format!("The list {} has been created.", escape_html(&name))).send()
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            FlashMessage::error("There is already a list with this name.").send()
+        }
+        Err(e) => {
+            return Err(e500(
+                anyhow::Error::from(e).context("Failed to store the new list"),
+            ));
+        }
+    }
+    Ok(see_other("/admin/lists"))
+}
@@ -1,9 +1,19 @@
 mod dashboard;
+mod email;
+mod lists;
 mod logout;
 mod newsletters;
 mod password;
+mod subscribers;
+mod two_factor;
+mod users;
 
 pub use dashboard::admin_dashboard;
+pub use email::*;
+pub use lists::*;
 pub use logout::*;
 pub use newsletters::*;
 pub use password::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
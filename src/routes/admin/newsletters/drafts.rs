@@ -0,0 +1,362 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::{SubscriberEmail, TemplateValues};
+use crate::email_client::EmailTransport;
+use crate::idempotency::{IdempotencyKey, NextAction, save_response, try_processing};
+use crate::issue_delivery_workers::{enque_delivery_tasks, render_content};
+use crate::lists::{get_lists, list_checkboxes, set_issue_lists};
+use crate::util::{e400, e500, escape_html, get_user_email, see_other};
+
+use super::post::{parse_form, parse_segment, success, text_or_generated, validate_templates};
+use super::preview::render_preview;
+
+#[derive(serde::Deserialize)]
+pub struct DraftForm {
+    title: String,
+    html: String,
+    /// Left empty to have it generated from the html content when the draft is published.
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishForm {
+    idempotency_key: String,
+    #[serde(default)]
+    list_id: Vec<Uuid>,
+    segment: Option<String>,
+    #[serde(default)]
+    track_opens: bool,
+    #[serde(default)]
+    track_clicks: bool,
+}
+
+struct Draft {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+fn draft_url(newsletter_issue_id: Uuid) -> String {
+    format!("/admin/newsletters/drafts/{newsletter_issue_id}")
+}
+
+pub async fn get_drafts(
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let drafts = sqlx::query!(
+        "SELECT newsletter_issue_id, title FROM newsletter_issues WHERE status = 'draft' ORDER BY title"
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch newsletter drafts")
+    .map_err(e500)?;
+
+    let mut items = String::new();
+    for draft in drafts {
+        write!(
+            &mut items,
+            r#"<li><a href="{}">{}</a></li>"#,
+            draft_url(draft.newsletter_issue_id),
+            escape_html(&draft.title)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("drafts.html"), messages, items)))
+}
+
+#[tracing::instrument(name = "Create newsletter draft", skip_all)]
+pub async fn create_draft(
+    form: web::Form<DraftForm>,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues(
+            newsletter_issue_id,
+            title,
+            html_content,
+            text_content,
+            status
+            )
+        VALUES($1, $2, $3, $4, 'draft')
+        "#,
+        newsletter_issue_id,
+        form.title,
+        form.html,
+        form.text
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to store newsletter draft")
+    .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_url(newsletter_issue_id)))
+}
+
+pub async fn get_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let Some(draft) = fetch_draft(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let lists = get_lists(pool.as_ref()).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("draft.html"),
+            messages = messages,
+            id = newsletter_issue_id,
+            title = escape_html(&draft.title),
+            html = escape_html(&draft.html_content),
+            text = escape_html(&draft.text_content),
+            lists = list_checkboxes(&lists),
+            idempotency_key = Uuid::new_v4()
+        )))
+}
+
+#[tracing::instrument(name = "Update newsletter draft", skip(form, pool, _user_id))]
+pub async fn update_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<DraftForm>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, html_content = $3, text_content = $4
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id,
+        form.title,
+        form.html,
+        form.text
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update newsletter draft")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_url(newsletter_issue_id)))
+}
+
+pub async fn preview_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let Some(draft) = fetch_draft(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    render_preview(
+        &draft.title,
+        &draft.html_content,
+        draft.text_content,
+        &format!(
+            r#"<a href="{}">Back to the draft</a>"#,
+            draft_url(newsletter_issue_id)
+        ),
+    )
+}
+
+/// Send the draft to the logged in user only, without going through the delivery queue.
+#[tracing::instrument(
+    name = "Send test of newsletter draft",
+    skip(pool, email_client, user_id)
+)]
+pub async fn send_test_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let Some(draft) = fetch_draft(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let email = get_user_email(&pool, **user_id).await.map_err(e500)?;
+    let Some(email) = email.and_then(|e| SubscriberEmail::parse(e).ok()) else {
+        FlashMessage::error(
+            r#"You need to <a href="/admin/email">set your email address</a> to receive test emails."#,
+        )
+        .send();
+        return Ok(see_other(&draft_url(newsletter_issue_id)));
+    };
+
+    // There is no subscriber to take the other values from, so their fallbacks show instead.
+    let values = TemplateValues {
+        email: Some(email.as_ref()),
+        ..Default::default()
+    };
+    let text_content = text_or_generated(&draft.html_content, Some(draft.text_content))?;
+    match email_client
+        .send_email(
+            &email,
+            &format!("[TEST] {}", draft.title),
+            &render_content(&draft.html_content, |t| t.render_html(&values)),
+            &render_content(&text_content, |t| t.render_text(&values)),
+            &[],
+        )
+        .await
+    {
+        Ok(_) => FlashMessage::info(format!(
+            "A test email has been sent to {}.",
+            escape_html(email.as_ref())
+        ))
+        .send(),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to send test email");
+            FlashMessage::error("Failed to send the test email.").send();
+        }
+    }
+    Ok(see_other(&draft_url(newsletter_issue_id)))
+}
+
+#[tracing::instrument(name = "Publish newsletter draft", skip(body, pool, user_id))]
+pub async fn publish_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let user_id = user_id.into_inner();
+    let form: PublishForm = parse_form(&body)?;
+    let idempotency_key: IdempotencyKey = form.idempotency_key.try_into().map_err(e400)?;
+    let segment = parse_segment(form.segment)?;
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(res) => {
+            success(None);
+            return Ok(res);
+        }
+    };
+
+    let Some(draft) = fetch_draft(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let text_content = text_or_generated(&draft.html_content, Some(draft.text_content))?;
+    validate_templates(&draft.html_content, &text_content)?;
+
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'published',
+            published_at = now(),
+            segment = $2,
+            text_content = $3,
+            track_opens = $4,
+            track_clicks = $5
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id,
+        segment,
+        text_content,
+        form.track_opens,
+        form.track_clicks
+    );
+    let published = transaction
+        .execute(query)
+        .await
+        .context("Failed to publish newsletter draft")
+        .map_err(e500)?
+        .rows_affected();
+    if published == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    if !set_issue_lists(&mut transaction, newsletter_issue_id, &form.list_id)
+        .await
+        .map_err(e500)?
+    {
+        return Err(e400("One of the lists does not exist"));
+    }
+
+    enque_delivery_tasks(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+
+    success(None);
+    let response = see_other("/admin/newsletters/drafts");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    Ok(response)
+}
+
+async fn fetch_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT title, html_content, text_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch newsletter draft")?;
+    Ok(draft)
+}
@@ -1,19 +1,28 @@
 use std::fmt::Write;
 
-use actix_web::HttpResponse;
+use actix_web::{HttpResponse, web};
 use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
 
-pub async fn get_newsletters(received: IncomingFlashMessages) -> HttpResponse {
+use crate::lists::{get_lists, list_checkboxes};
+use crate::util::e500;
+
+pub async fn get_newsletters(
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
     let mut messages = String::new();
     for msg in received.iter() {
         // This should never throw an error
         write!(&mut messages, "{}", msg.content()).unwrap();
     }
-    HttpResponse::Ok()
+    let lists = get_lists(pool.as_ref()).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
         .content_type(actix_web::http::header::ContentType::html())
         .body(format!(
             include_str!("newsletters.html"),
             messages,
+            list_checkboxes(&lists),
             uuid::Uuid::new_v4() // idempotency key
-        ))
+        )))
 }
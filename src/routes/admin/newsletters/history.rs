@@ -0,0 +1,54 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html};
+
+pub async fn get_newsletter_history(
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.published_at AS "published_at!",
+            COUNT(d.subscriber_email) AS "recipients!"
+        FROM newsletter_issues i
+        LEFT JOIN issue_deliveries d ON d.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.status = 'published'
+        GROUP BY i.newsletter_issue_id
+        ORDER BY i.published_at DESC
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch published newsletter issues")
+    .map_err(e500)?;
+
+    let mut rows = String::new();
+    for issue in issues {
+        let id = issue.newsletter_issue_id;
+        write!(
+            &mut rows,
+            r#"<tr>
+                <td><a href="/admin/newsletters/{id}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td><a href="/issues/{id}">View</a></td>
+            </tr>"#,
+            escape_html(&issue.title),
+            issue.published_at.format("%Y-%m-%d %H:%M"),
+            issue.recipients,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("history.html"), rows)))
+}
@@ -1,5 +1,20 @@
+mod drafts;
 mod get;
+mod history;
 mod post;
+mod preview;
+mod recipients;
+mod report;
+mod scheduled;
 
+pub use drafts::{
+    create_draft, get_draft, get_drafts, preview_draft, publish_draft, send_test_draft,
+    update_draft,
+};
 pub use get::get_newsletters;
+pub use history::get_newsletter_history;
 pub use post::post_newsletters;
+pub use preview::preview_newsletter;
+pub use recipients::count_issue_recipients;
+pub use report::get_newsletter_report;
+pub use scheduled::{cancel_newsletter, get_scheduled_newsletters, reschedule_newsletter};
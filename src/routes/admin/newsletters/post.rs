@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::Executor;
 
@@ -9,21 +10,46 @@ use sqlx::Postgres;
 use sqlx::Transaction;
 use uuid::Uuid;
 
+use super::scheduled::{check_schedule_time, parse_schedule_time};
+
 use crate::authentication::UserId;
+use crate::domain::{IssueTemplate, Segment};
 use crate::idempotency::IdempotencyKey;
 use crate::idempotency::NextAction;
 use crate::idempotency::save_response;
 use crate::idempotency::try_processing;
+use crate::issue_delivery_workers::enque_delivery_tasks;
+use crate::lists::set_issue_lists;
 use crate::util::e400;
 use crate::util::e500;
+use crate::util::html_to_text;
 use crate::util::see_other;
 
 #[derive(Deserialize)]
 pub struct BodyData {
     title: String,
     html: String,
-    text: String,
+    /// Generated from the html content when left empty.
+    text: Option<String>,
+    /// Leave empty to publish right away.
+    scheduled_for: Option<String>,
     idempotency_key: String,
+    /// The lists to send the issue to, the default list if none is picked.
+    #[serde(default)]
+    list_id: Vec<Uuid>,
+    segment: Option<String>,
+    /// Whether delivered emails report being opened, if tracking is enabled.
+    #[serde(default)]
+    track_opens: bool,
+    /// Whether the links in delivered emails report being clicked, if tracking is enabled.
+    #[serde(default)]
+    track_clicks: bool,
+}
+
+/// What the delivered emails of an issue track.
+struct IssueTracking {
+    opens: bool,
+    clicks: bool,
 }
 
 #[tracing::instrument(
@@ -32,7 +58,7 @@ pub struct BodyData {
     fields(userid=%&*user_id)
 )]
 pub async fn post_newsletters(
-    body: web::Form<BodyData>,
+    body: web::Bytes,
     pg_pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
 ) -> Result<HttpResponse, actix_web::Error> {
@@ -42,10 +68,23 @@ pub async fn post_newsletters(
         title,
         html,
         text,
+        scheduled_for,
         idempotency_key,
-    } = body.0;
+        list_id: list_ids,
+        segment,
+        track_opens,
+        track_clicks,
+    } = parse_form(&body)?;
 
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let segment = parse_segment(segment)?;
+    let text = text_or_generated(&html, text)?;
+    validate_templates(&html, &text)?;
+    let scheduled_for = scheduled_for
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| parse_schedule_time(&s))
+        .transpose()
+        .map_err(e400)?;
 
     let mut transaction = match try_processing(&pg_pool, &idempotency_key, *user_id)
         .await
@@ -53,20 +92,44 @@ pub async fn post_newsletters(
     {
         NextAction::StartProcessing(transaction) => transaction,
         NextAction::ReturnSavedResponse(res) => {
-            success();
+            success(scheduled_for);
             return Ok(res);
         }
     };
+    let scheduled_for = scheduled_for
+        .map(check_schedule_time)
+        .transpose()
+        .map_err(e400)?;
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &html, &text)
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &html,
+        &text,
+        segment.as_deref(),
+        IssueTracking {
+            opens: track_opens,
+            clicks: track_clicks,
+        },
+        scheduled_for,
+    )
+    .await
+    .map_err(e500)?;
+    if !set_issue_lists(&mut transaction, issue_id, &list_ids)
         .await
-        .map_err(e500)?;
+        .map_err(e500)?
+    {
+        return Err(e400("One of the lists does not exist"));
+    }
 
-    enque_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .map_err(e500)?;
+    // Scheduled issues are queued for delivery by the scheduling worker once they are due.
+    if scheduled_for.is_none() {
+        enque_delivery_tasks(&mut transaction, issue_id)
+            .await
+            .map_err(e500)?;
+    }
 
-    success();
+    success(scheduled_for);
     let response = see_other("/admin/newsletters");
     let response = save_response(transaction, &idempotency_key, *user_id, response)
         .await
@@ -74,8 +137,52 @@ pub async fn post_newsletters(
     Ok(response)
 }
 
-pub fn success() {
-    FlashMessage::info("The newsletter has been published!".to_string()).send();
+/// `web::Form` cannot collect the values of a repeated field, which checkboxes sharing a name
+/// are submitted as.
+pub fn parse_form<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, actix_web::Error> {
+    serde_html_form::from_bytes(body).map_err(e400)
+}
+
+/// Check the segment of an issue, which is left empty to send it to every subscriber of its
+/// lists.
+pub fn parse_segment(segment: Option<String>) -> Result<Option<String>, actix_web::Error> {
+    let Some(segment) = segment
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+    else {
+        return Ok(None);
+    };
+    Segment::parse(&segment).map_err(e400)?;
+    Ok(Some(segment))
+}
+
+/// The plain text part of an issue, generated from the html part if it was left empty.
+pub fn text_or_generated(
+    html_content: &str,
+    text_content: Option<String>,
+) -> Result<String, actix_web::Error> {
+    match text_content.filter(|t| !t.trim().is_empty()) {
+        Some(text_content) => Ok(text_content),
+        None => html_to_text(html_content).map_err(e500),
+    }
+}
+
+/// Reject an issue using variables that do not exist, before it reaches any subscriber.
+pub fn validate_templates(html_content: &str, text_content: &str) -> Result<(), actix_web::Error> {
+    IssueTemplate::parse(html_content).map_err(|e| e400(format!("Html content: {e}")))?;
+    IssueTemplate::parse(text_content).map_err(|e| e400(format!("Plaintext content: {e}")))?;
+    Ok(())
+}
+
+pub fn success(scheduled_for: Option<DateTime<Utc>>) {
+    match scheduled_for {
+        Some(scheduled_for) => FlashMessage::info(format!(
+            "The newsletter has been scheduled for {}!",
+            scheduled_for.format("%Y-%m-%d %H:%M UTC")
+        ))
+        .send(),
+        None => FlashMessage::info("The newsletter has been published!".to_string()).send(),
+    }
 }
 
 #[tracing::instrument(name = "Insert newsletter issue", skip_all)]
@@ -84,8 +191,16 @@ async fn insert_newsletter_issue(
     title: &str,
     html_content: &str,
     text_content: &str,
+    segment: Option<&str>,
+    tracking: IssueTracking,
+    scheduled_for: Option<DateTime<Utc>>,
 ) -> Result<Uuid, anyhow::Error> {
     let newsletter_issue_id = Uuid::new_v4();
+    let status = if scheduled_for.is_some() {
+        "scheduled"
+    } else {
+        "published"
+    };
 
     let query = sqlx::query!(
         r#"
@@ -94,35 +209,27 @@ async fn insert_newsletter_issue(
             title,
             html_content,
             text_content,
+            status,
+            segment,
+            track_opens,
+            track_clicks,
+            scheduled_for,
             published_at
             )
-        VALUES($1, $2, $3, $4, now())
+        VALUES(
+            $1, $2, $3, $4, $5, $6, $7, $8, $9, CASE WHEN $9::timestamptz IS NULL THEN now() END
+        )
         "#,
         newsletter_issue_id,
         title,
         html_content,
-        text_content
+        text_content,
+        status,
+        segment,
+        tracking.opens,
+        tracking.clicks,
+        scheduled_for
     );
     transaction.execute(query).await?;
     Ok(newsletter_issue_id)
 }
-
-async fn enque_delivery_tasks(
-    transaction: &mut Transaction<'_, Postgres>,
-    newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    let query = sqlx::query!(
-        r#"
-        INSERT INTO issue_delivery_queue(
-            newsletter_issue_id,
-            subscriber_email
-            )
-            SELECT $1, email FROM subscriptions
-            WHERE status = 'confirmed'
-        "#,
-        newsletter_issue_id
-    );
-
-    transaction.execute(query).await?;
-    Ok(())
-}
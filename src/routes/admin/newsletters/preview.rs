@@ -0,0 +1,51 @@
+use actix_web::{HttpResponse, http::header::ContentType, web};
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html, html_to_text};
+
+use super::post::parse_form;
+
+/// The fields of the publish form shown in the preview, the others are ignored.
+#[derive(serde::Deserialize)]
+pub struct PreviewForm {
+    title: String,
+    html: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// Preview an issue from the publish form before it is sent or saved.
+pub async fn preview_newsletter(
+    body: web::Bytes,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let PreviewForm { title, html, text } = parse_form(&body)?;
+    render_preview(&title, &html, text, "")
+}
+
+/// Show both parts of an issue, generating the plain text part if it was left empty.
+pub fn render_preview(
+    title: &str,
+    html_content: &str,
+    text_content: String,
+    back_link: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (text_content, text_note) = if text_content.trim().is_empty() {
+        let generated = html_to_text(html_content).map_err(e500)?;
+        (generated, "<p><i>Generated from the html content.</i></p>")
+    } else {
+        (text_content, "")
+    };
+
+    // The html part is rendered in a sandboxed frame so that it cannot affect the admin page.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("preview.html"),
+            title = escape_html(title),
+            html = escape_html(html_content),
+            text = escape_html(&text_content),
+            text_note = text_note,
+            back_link = back_link
+        )))
+}
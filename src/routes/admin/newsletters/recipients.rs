@@ -0,0 +1,50 @@
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::Segment;
+use crate::issue_delivery_workers::count_recipients;
+use crate::lists::get_default_list;
+use crate::util::{e400, e500};
+
+use super::post::parse_form;
+
+/// The fields of the publish forms the recipients depend on. The others are ignored, so the
+/// forms can be submitted here as they are.
+#[derive(serde::Deserialize)]
+pub struct RecipientsForm {
+    #[serde(default)]
+    list_id: Vec<Uuid>,
+    segment: Option<String>,
+}
+
+/// Show how many subscribers an issue would be delivered to, before publishing it.
+#[tracing::instrument(name = "Count recipients", skip_all)]
+pub async fn count_issue_recipients(
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form: RecipientsForm = parse_form(&body)?;
+    let segment = form
+        .segment
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(Segment::parse)
+        .transpose()
+        .map_err(e400)?;
+    let list_ids = if form.list_id.is_empty() {
+        vec![get_default_list(pool.as_ref()).await.map_err(e500)?.list_id]
+    } else {
+        form.list_id
+    };
+
+    let n_recipients = count_recipients(pool.as_ref(), list_ids, segment.as_ref())
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("recipients.html"), n_recipients)))
+}
@@ -0,0 +1,276 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html, page_offset};
+
+const PAGE_SIZE: i64 = 50;
+/// How many of the most clicked links are listed.
+const TOP_LINKS: i64 = 10;
+
+#[derive(serde::Deserialize)]
+pub struct ReportParameters {
+    page: Option<i64>,
+}
+
+#[tracing::instrument(
+    name = "Get newsletter delivery report",
+    skip(pool, parameters, _user_id)
+)]
+pub async fn get_newsletter_report(
+    newsletter_issue_id: web::Path<Uuid>,
+    parameters: web::Query<ReportParameters>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let page = parameters.page.unwrap_or(1);
+    let offset = page_offset(page, PAGE_SIZE)?;
+
+    let Some(issue) = get_issue(&pool, newsletter_issue_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let totals = get_delivery_totals(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let deliveries = get_deliveries(&pool, newsletter_issue_id, offset)
+        .await
+        .map_err(e500)?;
+    let engagement = if issue.track_opens || issue.track_clicks {
+        render_engagement(&pool, &issue, newsletter_issue_id, totals.sent)
+            .await
+            .map_err(e500)?
+    } else {
+        "<p>This issue does not track opens or clicks.</p>".to_string()
+    };
+
+    let mut rows = String::new();
+    for delivery in &deliveries {
+        write!(
+            &mut rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&delivery.subscriber_email),
+            delivery.status,
+            delivery.n_attempts,
+            escape_html(delivery.provider_message_id.as_deref().unwrap_or_default()),
+            delivery.updated_at.to_rfc3339()
+        )
+        .unwrap();
+    }
+
+    let mut pagination = String::new();
+    if page > 1 {
+        write!(
+            &mut pagination,
+            r#"<a href="/admin/newsletters/{newsletter_issue_id}?page={}">Previous</a> "#,
+            page - 1
+        )
+        .unwrap();
+    }
+    if totals.total - offset > PAGE_SIZE {
+        write!(
+            &mut pagination,
+            r#"<a href="/admin/newsletters/{newsletter_issue_id}?page={}">Next</a>"#,
+            page + 1
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("report.html"),
+            escape_html(&issue.title),
+            totals.queued,
+            totals.sent,
+            totals.failed,
+            totals.skipped_invalid_email,
+            totals.skipped_unsubscribed,
+            totals.skipped_suppressed,
+            engagement,
+            rows,
+            pagination
+        )))
+}
+
+/// How many of the subscribers the issue was sent to opened it and clicked its links, and which
+/// links were clicked most.
+async fn render_engagement(
+    pool: &PgPool,
+    issue: &Issue,
+    newsletter_issue_id: Uuid,
+    n_sent: i64,
+) -> Result<String, anyhow::Error> {
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'open') AS "opened!",
+            COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'click') AS "clicked!"
+        FROM issue_tracking_events
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count tracking events")?;
+    let rate = |n: i64| {
+        if n_sent == 0 {
+            "-".to_string()
+        } else {
+            format!("{:.1}%", 100.0 * n as f64 / n_sent as f64)
+        }
+    };
+
+    let mut engagement = String::from("<table>");
+    if issue.track_opens {
+        write!(
+            &mut engagement,
+            "<tr><th>Open rate</th><td>{} ({} subscribers)</td></tr>",
+            rate(totals.opened),
+            totals.opened
+        )
+        .unwrap();
+    }
+    if issue.track_clicks {
+        write!(
+            &mut engagement,
+            "<tr><th>Click rate</th><td>{} ({} subscribers)</td></tr>",
+            rate(totals.clicked),
+            totals.clicked
+        )
+        .unwrap();
+    }
+    engagement.push_str("</table>");
+    if !issue.track_clicks {
+        return Ok(engagement);
+    }
+
+    let links = sqlx::query!(
+        r#"
+        SELECT url AS "url!", COUNT(*) AS "clicks!", COUNT(DISTINCT subscriber_id) AS "subscribers!"
+        FROM issue_tracking_events
+        WHERE newsletter_issue_id = $1 AND kind = 'click'
+        GROUP BY url
+        ORDER BY 2 DESC, url
+        LIMIT $2
+        "#,
+        newsletter_issue_id,
+        TOP_LINKS
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the most clicked links")?;
+    engagement.push_str(
+        "<h3>Top links</h3><table><tr><th>Link</th><th>Clicks</th><th>Subscribers</th></tr>",
+    );
+    for link in links {
+        write!(
+            &mut engagement,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&link.url),
+            link.clicks,
+            link.subscribers
+        )
+        .unwrap();
+    }
+    engagement.push_str("</table>");
+    Ok(engagement)
+}
+
+struct Issue {
+    title: String,
+    track_opens: bool,
+    track_clicks: bool,
+}
+
+struct DeliveryTotals {
+    total: i64,
+    queued: i64,
+    sent: i64,
+    failed: i64,
+    skipped_invalid_email: i64,
+    skipped_unsubscribed: i64,
+    skipped_suppressed: i64,
+}
+
+struct Delivery {
+    subscriber_email: String,
+    status: String,
+    n_attempts: i16,
+    provider_message_id: Option<String>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+async fn get_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Issue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        Issue,
+        r#"
+        SELECT title, track_opens, track_clicks
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch newsletter issue")?;
+    Ok(issue)
+}
+
+async fn get_delivery_totals(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<DeliveryTotals, anyhow::Error> {
+    let totals = sqlx::query_as!(
+        DeliveryTotals,
+        r#"
+        SELECT
+            COUNT(*) AS "total!",
+            COUNT(*) FILTER (WHERE status = 'queued') AS "queued!",
+            COUNT(*) FILTER (WHERE status = 'sent') AS "sent!",
+            COUNT(*) FILTER (WHERE status = 'failed') AS "failed!",
+            COUNT(*) FILTER (WHERE status = 'skipped_invalid_email') AS "skipped_invalid_email!",
+            COUNT(*) FILTER (WHERE status = 'skipped_unsubscribed') AS "skipped_unsubscribed!",
+            COUNT(*) FILTER (WHERE status = 'skipped_suppressed') AS "skipped_suppressed!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count issue deliveries")?;
+    Ok(totals)
+}
+
+async fn get_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    offset: i64,
+) -> Result<Vec<Delivery>, anyhow::Error> {
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT subscriber_email, status, n_attempts, provider_message_id, updated_at
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        ORDER BY subscriber_email
+        LIMIT $2 OFFSET $3
+        "#,
+        newsletter_issue_id,
+        PAGE_SIZE,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch issue deliveries")?;
+    Ok(deliveries)
+}
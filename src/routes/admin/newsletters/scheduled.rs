@@ -0,0 +1,202 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html, see_other};
+
+/// Parse the time an issue should be published at, see [`check_schedule_time`] for whether it
+/// can still be.
+///
+/// Accepts RFC 3339 timestamps as well as the timezone-less values produced by `datetime-local`
+/// inputs, which are interpreted as UTC.
+///
+/// The error quotes the input escaped, as it is shown back to the user in a flash message.
+pub fn parse_schedule_time(s: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    let time = DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M").map(|t| t.and_utc()))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").map(|t| t.and_utc()))
+        .map_err(|_| {
+            anyhow!(
+                "'{}' is not a valid time to schedule a newsletter for.",
+                escape_html(s)
+            )
+        })?;
+    Ok(time)
+}
+
+/// Only let issues be scheduled for a time which has not passed yet.
+///
+/// Kept apart from [`parse_schedule_time`] so that a publish replayed with its idempotency key
+/// after its time gets the saved response rather than an error.
+pub fn check_schedule_time(time: DateTime<Utc>) -> Result<DateTime<Utc>, anyhow::Error> {
+    if time <= Utc::now() {
+        anyhow::bail!("Newsletters can only be scheduled for a time in the future.");
+    }
+    Ok(time)
+}
+
+pub async fn get_scheduled_newsletters(
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, scheduled_for AS "scheduled_for!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY scheduled_for
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch scheduled newsletter issues")
+    .map_err(e500)?;
+
+    let mut rows = String::new();
+    for issue in issues {
+        let id = issue.newsletter_issue_id;
+        write!(
+            &mut rows,
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td><form action="/admin/newsletters/scheduled/{id}/reschedule" method="post">
+                    <input name="scheduled_for" type="datetime-local" required>
+                    <input type="submit" value="Reschedule">
+                </form></td>
+                <td><form action="/admin/newsletters/scheduled/{id}/cancel" method="post">
+                    <input type="submit" value="Cancel">
+                </form></td>
+            </tr>"#,
+            escape_html(&issue.title),
+            issue.scheduled_for.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("scheduled.html"), messages, rows)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleForm {
+    scheduled_for: String,
+}
+
+#[tracing::instrument(name = "Reschedule newsletter", skip(form, pool, _user_id))]
+pub async fn reschedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<RescheduleForm>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let scheduled_for = match parse_schedule_time(&form.scheduled_for).and_then(check_schedule_time)
+    {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+    };
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        *newsletter_issue_id,
+        scheduled_for
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to reschedule newsletter issue")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated == 0 {
+        FlashMessage::error("This newsletter is no longer scheduled.").send();
+    } else {
+        FlashMessage::info(format!(
+            "The newsletter has been rescheduled for {}.",
+            scheduled_for.format("%Y-%m-%d %H:%M UTC")
+        ))
+        .send();
+    }
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(name = "Cancel scheduled newsletter", skip(pool, _user_id))]
+pub async fn cancel_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        *newsletter_issue_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to cancel newsletter issue")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated == 0 {
+        FlashMessage::error("This newsletter is no longer scheduled.").send();
+    } else {
+        FlashMessage::info("The newsletter has been cancelled.").send();
+    }
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_schedule_time, parse_schedule_time};
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn rfc3339_time_is_valid() {
+        let time = (Utc::now() + Duration::days(1)).to_rfc3339();
+        assert_ok!(parse_schedule_time(&time));
+    }
+
+    #[test]
+    fn datetime_local_time_is_valid() {
+        let time = (Utc::now() + Duration::days(1))
+            .format("%Y-%m-%dT%H:%M")
+            .to_string();
+        assert_ok!(parse_schedule_time(&time));
+    }
+
+    #[test]
+    fn time_in_the_past_err() {
+        let time = (Utc::now() - Duration::minutes(1)).to_rfc3339();
+        assert_err!(parse_schedule_time(&time).and_then(check_schedule_time));
+    }
+
+    #[test]
+    fn garbage_err() {
+        assert_err!(parse_schedule_time("tomorrow"));
+    }
+}
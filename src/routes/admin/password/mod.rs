@@ -3,3 +3,4 @@ pub mod post;
 
 pub use get::change_password_form;
 pub use post::change_password;
+pub(crate) use post::validate_password_strength;
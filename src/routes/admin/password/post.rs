@@ -58,7 +58,9 @@ pub async fn change_password(
     Ok(see_other("/admin/change-password"))
 }
 
-fn validate_password_strength(password: &Secret<String>) -> Result<(), PasswordChangeError> {
+pub(crate) fn validate_password_strength(
+    password: &Secret<String>,
+) -> Result<(), PasswordChangeError> {
     if password.expose_secret().graphemes(true).count() > 128 {
         return Err(PasswordChangeError::PasswordTooLong);
     }
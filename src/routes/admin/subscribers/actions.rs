@@ -0,0 +1,195 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::issue_delivery_workers::cancel_queued_deliveries;
+use crate::routes::unsubscribe_from_all_lists;
+use crate::util::{e500, escape_html, see_other};
+
+fn subscriber_url(subscriber_id: Uuid) -> String {
+    format!("/admin/subscribers/{subscriber_id}")
+}
+
+/// Confirm a subscriber and every subscription they have pending on their behalf, e.g. when
+/// they could not use their confirmation link.
+#[tracing::instrument(name = "Manually confirm subscriber", skip(pool, _user_id))]
+pub async fn mark_subscriber_confirmed(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+
+    if confirm_all_subscriptions(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        delete_tokens(&mut transaction, subscriber_id)
+            .await
+            .map_err(e500)?;
+        FlashMessage::info("The subscriber has been confirmed.").send();
+    } else {
+        FlashMessage::error("Only subscriptions pending confirmation can be confirmed.").send();
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit subscriber confirmation.")
+        .map_err(e500)?;
+    Ok(see_other(&subscriber_url(subscriber_id)))
+}
+
+#[tracing::instrument(name = "Manually unsubscribe subscriber", skip(pool, _user_id))]
+pub async fn mark_subscriber_unsubscribed(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+
+    let subscriber = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = $1 AND status <> 'unsubscribed'
+        RETURNING email
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to unsubscribe subscriber")
+    .map_err(e500)?;
+
+    match subscriber {
+        Some(subscriber) => {
+            unsubscribe_from_all_lists(&mut transaction, subscriber_id)
+                .await
+                .map_err(e500)?;
+            delete_tokens(&mut transaction, subscriber_id)
+                .await
+                .map_err(e500)?;
+            cancel_queued_deliveries(&mut transaction, &subscriber.email)
+                .await
+                .context("Failed to cancel queued deliveries")
+                .map_err(e500)?;
+            FlashMessage::info("The subscriber has been unsubscribed.").send();
+        }
+        None => FlashMessage::error("This subscriber is already unsubscribed.").send(),
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit unsubscription.")
+        .map_err(e500)?;
+    Ok(see_other(&subscriber_url(subscriber_id)))
+}
+
+/// Delete a subscriber for good. Their delivery history is kept for the issue reports.
+#[tracing::instrument(name = "Delete subscriber", skip(pool, _user_id))]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+
+    // The subscriber's confirmation tokens are deleted along with them.
+    let subscriber = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete subscriber")
+    .map_err(e500)?;
+
+    let Some(subscriber) = subscriber else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    cancel_queued_deliveries(&mut transaction, &subscriber.email)
+        .await
+        .context("Failed to cancel queued deliveries")
+        .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit subscriber deletion.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "{} has been deleted.",
+        escape_html(&subscriber.email)
+    ))
+    .send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+/// Returns whether the subscriber or any of their subscriptions was pending confirmation.
+async fn confirm_all_subscriptions(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let confirmed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to confirm subscriber")?
+    .rows_affected();
+    let confirmed_lists = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions l SET status = 'confirmed'
+        FROM subscriptions s
+        WHERE
+            s.id = l.subscriber_id
+            AND l.subscriber_id = $1
+            AND l.status = 'pending_confirmation'
+            AND s.status = 'confirmed'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to confirm list subscriptions")?
+    .rows_affected();
+    Ok(confirmed + confirmed_lists > 0)
+}
+
+async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete subscription tokens")?;
+    Ok(())
+}
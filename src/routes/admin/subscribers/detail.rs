@@ -0,0 +1,166 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html};
+
+#[tracing::instrument(name = "Get subscriber", skip(received, pool, _user_id))]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let Some(subscriber) = sqlx::query!(
+        "SELECT email, name, status, subscribed_at FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to fetch subscriber")
+    .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.status, d.n_attempts, d.updated_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1
+        ORDER BY d.queued_at DESC
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch subscriber deliveries")
+    .map_err(e500)?;
+
+    let memberships = sqlx::query!(
+        r#"
+        SELECT l.name, s.status, s.subscribed_at
+        FROM list_subscriptions s
+        JOIN lists l ON l.list_id = s.list_id
+        WHERE s.subscriber_id = $1
+        ORDER BY l.created_at, l.list_id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch subscriber lists")
+    .map_err(e500)?;
+
+    let mut lists = String::new();
+    for membership in &memberships {
+        write!(
+            &mut lists,
+            "<tr><td>{}</td><td>{}</td><td>{} UTC</td></tr>",
+            escape_html(&membership.name),
+            membership.status,
+            membership.subscribed_at.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    let subscriber_tags = sqlx::query!(
+        "SELECT key, value FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY key",
+        subscriber_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch subscriber tags")
+    .map_err(e500)?;
+
+    let mut tags = String::new();
+    for tag in subscriber_tags {
+        let key = escape_html(&tag.key);
+        write!(
+            &mut tags,
+            r#"<tr>
+                <td>{key}</td>
+                <td>{}</td>
+                <td><form action="/admin/subscribers/{subscriber_id}/tags/delete" method="post">
+                    <input type="hidden" name="key" value="{key}">
+                    <input type="submit" value="Remove">
+                </form></td>
+            </tr>"#,
+            escape_html(&tag.value),
+        )
+        .unwrap();
+    }
+
+    let mut rows = String::new();
+    for delivery in deliveries {
+        write!(
+            &mut rows,
+            r#"<tr>
+                <td><a href="/admin/newsletters/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            delivery.newsletter_issue_id,
+            escape_html(&delivery.title),
+            delivery.status,
+            delivery.n_attempts,
+            delivery.updated_at.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    let mut actions = String::new();
+    let has_pending_list = memberships
+        .iter()
+        .any(|m| m.status == "pending_confirmation");
+    if subscriber.status == "pending_confirmation"
+        || (subscriber.status == "confirmed" && has_pending_list)
+    {
+        write!(
+            &mut actions,
+            r#"<form action="/admin/subscribers/{subscriber_id}/confirm" method="post">
+                <input type="submit" value="Confirm">
+            </form>"#
+        )
+        .unwrap();
+    }
+    if subscriber.status != "unsubscribed" {
+        write!(
+            &mut actions,
+            r#"<form action="/admin/subscribers/{subscriber_id}/unsubscribe" method="post">
+                <input type="submit" value="Unsubscribe">
+            </form>"#
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("subscriber.html"),
+            messages = messages,
+            id = subscriber_id,
+            email = escape_html(&subscriber.email),
+            name = escape_html(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.format("%Y-%m-%d %H:%M"),
+            actions = actions,
+            lists = lists,
+            tags = tags,
+            rows = rows
+        )))
+}
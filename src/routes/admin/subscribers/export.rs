@@ -0,0 +1,83 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use futures_util::StreamExt;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+
+/// How many subscribers are fetched from the database at a time.
+const CHUNK_SIZE: i64 = 1000;
+
+struct Subscriber {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Stream every subscriber as CSV, in a format `/admin/subscribers/import` accepts.
+#[tracing::instrument(name = "Export subscribers", skip_all)]
+pub async fn export_subscribers(pool: web::Data<PgPool>, _: web::ReqData<UserId>) -> HttpResponse {
+    let header = csv_line(&["email", "name", "status", "subscribed_at"]);
+    let pool = pool.into_inner();
+    // Subscribers are fetched in chunks ordered by email, so that a large list is never held in
+    // memory nor a connection held for as long as the download takes.
+    let chunks = futures_util::stream::try_unfold(Some(String::new()), move |after| {
+        let pool = pool.clone();
+        async move {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+            let subscribers = sqlx::query_as!(
+                Subscriber,
+                r#"
+                SELECT email, name, status, subscribed_at
+                FROM subscriptions
+                WHERE email > $1
+                ORDER BY email
+                LIMIT $2
+                "#,
+                after,
+                CHUNK_SIZE
+            )
+            .fetch_all(pool.as_ref())
+            .await
+            .context("Failed to fetch subscribers")
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            let next = (subscribers.len() as i64 == CHUNK_SIZE)
+                .then(|| subscribers.last().map(|s| s.email.clone()))
+                .flatten();
+            let mut chunk = Vec::new();
+            for s in &subscribers {
+                chunk.extend(csv_line(&[
+                    &s.email,
+                    &s.name,
+                    &s.status,
+                    &s.subscribed_at.to_rfc3339(),
+                ]));
+            }
+            Ok::<_, actix_web::Error>(Some((web::Bytes::from(chunk), next)))
+        }
+    });
+    let body =
+        futures_util::stream::once(
+            async move { Ok::<_, actix_web::Error>(web::Bytes::from(header)) },
+        )
+        .chain(chunks);
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            r#"attachment; filename="subscribers.csv""#,
+        ))
+        .streaming(body)
+}
+
+fn csv_line(fields: &[&str]) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // Writing to memory cannot fail
+    writer.write_record(fields).unwrap();
+    writer.into_inner().unwrap()
+}
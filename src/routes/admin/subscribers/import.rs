@@ -0,0 +1,467 @@
+use std::fmt::Write;
+use std::io::Read;
+
+use actix_multipart::{Field, Multipart};
+use actix_web::{HttpResponse, http::header::ContentType, web, web::Bytes};
+use anyhow::Context;
+use futures_util::TryStreamExt;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailTransport;
+use crate::lists::{MailingList, get_default_list, get_list, get_lists, list_options};
+use crate::routes::subscription::{
+    get_subscription_token, send_email, store_token, subscribe_to_list,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::util::{e400, e500, escape_html};
+
+use super::STATUSES;
+
+/// The largest file that can be imported at once.
+const MAX_IMPORT_SIZE: usize = 10 * 1024 * 1024;
+/// The largest value accepted for the other fields of the form.
+const MAX_FIELD_SIZE: usize = 1024;
+/// How many chunks of the file, and parsed rows, can be waiting to be processed.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// A row of the imported file. Columns other than these are ignored, so that an export can be
+/// imported back as is.
+#[derive(serde::Deserialize)]
+struct ImportRow {
+    email: String,
+    name: String,
+    status: Option<String>,
+}
+
+/// A row of the file along with its line, or why it could not be read.
+type ParsedRow = (u64, Result<ImportRow, String>);
+
+/// The line, subscriber and token of a confirmation email to send.
+type Confirmation = (u64, NewSubscriber, String);
+
+#[derive(Default)]
+struct ImportReport {
+    n_imported: usize,
+    n_duplicates: usize,
+    n_confirmations_sent: usize,
+    /// The line of the file each error is for.
+    errors: Vec<(u64, String)>,
+}
+
+pub async fn import_subscribers_form(
+    pool: web::Data<PgPool>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(pool.as_ref()).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("import.html"),
+            list_options = list_options(&lists),
+            report = ""
+        )))
+}
+
+/// Import subscribers to a list from a CSV file with `email`, `name` and optionally `status`
+/// columns.
+///
+/// Rows without a status are imported as confirmed, or as pending confirmation with a
+/// confirmation email sent to them, depending on the `confirmation` field. Addresses which are
+/// already subscribed are left untouched.
+///
+/// The file is imported as it is uploaded, so it has to be the last field of the form.
+#[tracing::instrument(name = "Import subscribers", skip_all)]
+pub async fn import_subscribers(
+    mut payload: Multipart,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    _: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut send_confirmations = true;
+    let mut list_id = None;
+    let mut imported = None;
+    while let Some(mut field) = payload.try_next().await.map_err(e400)? {
+        let name = field.name().unwrap_or_default().to_owned();
+        if imported.is_some() && ["file", "confirmation", "list_id"].contains(&name.as_str()) {
+            return Err(e400("The file must be the last field of the form."));
+        }
+        if name == "file" {
+            imported = Some(import_file(&mut field, &pool, list_id, send_confirmations).await?);
+            continue;
+        }
+        let mut value = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(e400)? {
+            if value.len() + chunk.len() > MAX_FIELD_SIZE {
+                return Err(e400(format!("The {name} field is too large.")));
+            }
+            value.extend_from_slice(&chunk);
+        }
+        match name.as_str() {
+            "confirmation" => send_confirmations = value != b"confirmed",
+            "list_id" => {
+                let value = String::from_utf8_lossy(&value);
+                list_id = Some(Uuid::parse_str(&value).map_err(e400)?);
+            }
+            _ => {}
+        }
+    }
+    let Some((transaction, list, mut report, confirmations)) = imported else {
+        return Err(e400("No file was uploaded."));
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit imported subscribers.")
+        .map_err(e500)?;
+
+    for (line, subscriber, token) in confirmations {
+        let email = subscriber.email.as_ref().to_owned();
+        match send_email(
+            email_client.as_ref(),
+            subscriber,
+            &list,
+            &base_url.0,
+            &token,
+        )
+        .await
+        {
+            Ok(()) => report.n_confirmations_sent += 1,
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to send confirmation email");
+                report.errors.push((
+                    line,
+                    format!("Imported, but the confirmation email to {email} could not be sent"),
+                ));
+            }
+        }
+    }
+
+    let lists = get_lists(pool.as_ref()).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("import.html"),
+            list_options = list_options(&lists),
+            report = render_report(&report)
+        )))
+}
+
+/// Import the rows of the uploaded file in a transaction, returning it along with the
+/// confirmation emails to send once it is committed.
+///
+/// The chunks of the file are parsed on a blocking thread as they arrive, through bounded
+/// channels both ways, so that memory use does not grow with the size of the file.
+async fn import_file(
+    field: &mut Field,
+    pool: &PgPool,
+    list_id: Option<Uuid>,
+    send_confirmations: bool,
+) -> Result<
+    (
+        Transaction<'static, Postgres>,
+        MailingList,
+        ImportReport,
+        Vec<Confirmation>,
+    ),
+    actix_web::Error,
+> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+    let list = match list_id {
+        Some(list_id) => get_list(&mut *transaction, list_id)
+            .await
+            .map_err(e500)?
+            .ok_or_else(|| e400("The list does not exist"))?,
+        None => get_default_list(&mut *transaction).await.map_err(e500)?,
+    };
+
+    let (chunk_tx, chunk_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let (row_tx, mut row_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let parser = tokio::task::spawn_blocking(move || {
+        parse_csv(
+            ChunkReader {
+                chunks: chunk_rx,
+                current: Bytes::new(),
+            },
+            row_tx,
+        )
+    });
+
+    let feed = async move {
+        let mut size = 0;
+        while let Some(chunk) = field.try_next().await.map_err(e400)? {
+            size += chunk.len();
+            if size > MAX_IMPORT_SIZE {
+                return Err(e400("The file is too large to be imported at once."));
+            }
+            // The parser stops early if the header is invalid.
+            if chunk_tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    };
+    // Owning the receiving end means it is dropped on an error, which stops the parser and in
+    // turn the feed rather than leaving them waiting on each other.
+    let list_id = list.list_id;
+    let import = async move {
+        let mut report = ImportReport::default();
+        let mut confirmations = Vec::new();
+        while let Some((line, row)) = row_rx.recv().await {
+            let (subscriber, status) = match row.and_then(|row| parse_row(row, send_confirmations))
+            {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    report.errors.push((line, e));
+                    continue;
+                }
+            };
+
+            let Some(subscriber_id) = insert_subscriber(&mut transaction, &subscriber, status)
+                .await
+                .map_err(e500)?
+            else {
+                report.n_duplicates += 1;
+                continue;
+            };
+            report.n_imported += 1;
+            // Bouncing is about the address rather than the list, which they did subscribe to.
+            let list_status = if status == "bounced" {
+                "confirmed"
+            } else {
+                status
+            };
+            subscribe_to_list(&mut transaction, list_id, subscriber_id, list_status)
+                .await
+                .context("Failed to add imported subscriber to list")
+                .map_err(e500)?;
+
+            if status == "pending_confirmation" && send_confirmations {
+                let token = get_subscription_token();
+                store_token(&mut transaction, subscriber_id, list_id, &token)
+                    .await
+                    .context("Failed to store confirmation token into database")
+                    .map_err(e500)?;
+                confirmations.push((line, subscriber, token));
+            }
+        }
+        Ok::<_, actix_web::Error>((transaction, report, confirmations))
+    };
+
+    let (fed, imported) = tokio::join!(feed, import);
+    fed?;
+    let (transaction, report, confirmations) = imported?;
+    parser
+        .await
+        .context("Failed to parse the imported file")
+        .map_err(e500)?
+        .map_err(e400)?;
+    Ok((transaction, list, report, confirmations))
+}
+
+/// Parse the file, sending each row along with its line as soon as it is read.
+///
+/// Runs on a blocking thread, and stops early if the rows are no longer received.
+fn parse_csv(reader: impl Read, rows: mpsc::Sender<ParsedRow>) -> Result<(), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("The file is not valid CSV: {e}"))?
+        .clone();
+    if !headers.iter().any(|h| h == "email") || !headers.iter().any(|h| h == "name") {
+        return Err("The file must have a header with email and name columns.".into());
+    }
+
+    for record in reader.records() {
+        let row = match record {
+            Ok(record) => (
+                record.position().map_or(0, |p| p.line()),
+                record
+                    .deserialize::<ImportRow>(Some(&headers))
+                    .map_err(|e| e.to_string()),
+            ),
+            Err(e) => (e.position().map_or(0, |p| p.line()), Err(e.to_string())),
+        };
+        if rows.blocking_send(row).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Lets the blocking csv reader read the chunks of the file as they are uploaded.
+struct ChunkReader {
+    chunks: mpsc::Receiver<Bytes>,
+    current: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current.split_to(n));
+        Ok(n)
+    }
+}
+
+fn parse_row(
+    row: ImportRow,
+    send_confirmations: bool,
+) -> Result<(NewSubscriber, &'static str), String> {
+    let subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(&row.email).map_err(|e| format!("{e}: {}", row.email))?,
+        name: SubscriberName::parse(&row.name).map_err(|e| format!("{e}: {}", row.name))?,
+    };
+    let status = match row.status.as_deref() {
+        None | Some("") if send_confirmations => "pending_confirmation",
+        None | Some("") => "confirmed",
+        Some(status) => STATUSES
+            .into_iter()
+            .find(|s| *s == status)
+            .ok_or_else(|| format!("'{status}' is not a subscriber status"))?,
+    };
+    Ok((subscriber, status))
+}
+
+/// Returns `None` if the address is already subscribed.
+async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &NewSubscriber,
+    status: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES($1, $2, $3, now(), $4)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        status
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to insert imported subscriber")?;
+    Ok(row.map(|r| r.id))
+}
+
+fn render_report(report: &ImportReport) -> String {
+    let mut html = format!(
+        "<h2>Import results</h2>\
+        <p>{} subscribers imported, {} already subscribed, {} confirmation emails sent.</p>",
+        report.n_imported, report.n_duplicates, report.n_confirmations_sent
+    );
+    if !report.errors.is_empty() {
+        html.push_str("<table><tr><th>Line</th><th>Error</th></tr>");
+        for (line, error) in &report.errors {
+            write!(
+                &mut html,
+                "<tr><td>{line}</td><td>{}</td></tr>",
+                escape_html(error)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>");
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkReader, ImportRow, parse_csv, parse_row};
+    use actix_web::web::Bytes;
+    use claims::{assert_err, assert_ok};
+    use tokio::sync::mpsc;
+
+    fn parse_chunks(chunks: &[&'static str]) -> Result<Vec<(u64, String)>, String> {
+        let (chunk_tx, chunk_rx) = mpsc::channel(chunks.len());
+        for chunk in chunks {
+            chunk_tx
+                .try_send(Bytes::from_static(chunk.as_bytes()))
+                .unwrap();
+        }
+        drop(chunk_tx);
+        let (row_tx, mut row_rx) = mpsc::channel(16);
+        let reader = ChunkReader {
+            chunks: chunk_rx,
+            current: Bytes::new(),
+        };
+        parse_csv(reader, row_tx)?;
+        let mut rows = Vec::new();
+        while let Ok((line, row)) = row_rx.try_recv() {
+            rows.push((line, row.map_or_else(|e| e, |row| row.email)));
+        }
+        Ok(rows)
+    }
+
+    #[test]
+    fn rows_split_across_chunks_are_parsed() {
+        let rows = assert_ok!(parse_chunks(&[
+            "email,na",
+            "me\nursula@exa",
+            "mple.com,Ursula\noctavia@example.com,",
+            "Octavia\n",
+        ]));
+        assert_eq!(
+            rows,
+            [
+                (2, "ursula@example.com".to_owned()),
+                (3, "octavia@example.com".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn files_without_the_required_header_are_rejected() {
+        assert_err!(parse_chunks(&["address\nursula@example.com\n"]));
+    }
+
+    fn row(email: &str, status: Option<&str>) -> ImportRow {
+        ImportRow {
+            email: email.into(),
+            name: "Ursula".into(),
+            status: status.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn rows_without_a_status_follow_the_confirmation_choice() {
+        let (_, status) = assert_ok!(parse_row(row("ursula@example.com", None), true));
+        assert_eq!(status, "pending_confirmation");
+        let (_, status) = assert_ok!(parse_row(row("ursula@example.com", Some("")), false));
+        assert_eq!(status, "confirmed");
+    }
+
+    #[test]
+    fn an_explicit_status_is_kept() {
+        let (_, status) = assert_ok!(parse_row(
+            row("ursula@example.com", Some("unsubscribed")),
+            true
+        ));
+        assert_eq!(status, "unsubscribed");
+    }
+
+    #[test]
+    fn unknown_statuses_and_invalid_emails_are_rejected() {
+        assert!(parse_row(row("ursula@example.com", Some("vip")), true).is_err());
+        assert!(parse_row(row("not-an-email", None), true).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::util::{e400, e500, escape_html, page_offset};
+
+use super::STATUSES;
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct SearchParameters {
+    search: Option<String>,
+    status: Option<String>,
+    page: Option<i64>,
+}
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[tracing::instrument(name = "List subscribers", skip_all)]
+pub async fn get_subscribers(
+    received: IncomingFlashMessages,
+    parameters: web::Query<SearchParameters>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let page = parameters.page.unwrap_or(1);
+    let offset = page_offset(page, PAGE_SIZE)?;
+    let search = parameters.search.as_deref().unwrap_or_default().trim();
+    let status = parameters.status.as_deref().unwrap_or_default();
+    if !status.is_empty() && !STATUSES.contains(&status) {
+        return Err(e400(format!("'{status}' is not a subscriber status")));
+    }
+    let pattern = (!search.is_empty()).then(|| format!("%{}%", escape_like(search)));
+    let status_filter = (!status.is_empty()).then_some(status);
+
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status = $2)
+        "#,
+        pattern,
+        status_filter
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to count subscribers")
+    .map_err(e500)?
+    .count;
+
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3
+        OFFSET $4
+        "#,
+        pattern,
+        status_filter,
+        PAGE_SIZE,
+        offset
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch subscribers")
+    .map_err(e500)?;
+
+    let mut rows = String::new();
+    for subscriber in subscribers {
+        write!(
+            &mut rows,
+            r#"<tr>
+                <td><a href="/admin/subscribers/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            subscriber.id,
+            escape_html(&subscriber.email),
+            escape_html(&subscriber.name),
+            subscriber.status,
+            subscriber.subscribed_at.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    let mut status_options = String::new();
+    for option in STATUSES {
+        let selected = if option == status { " selected" } else { "" };
+        write!(
+            &mut status_options,
+            r#"<option value="{option}"{selected}>{option}</option>"#
+        )
+        .unwrap();
+    }
+
+    let page_url = |page: i64| {
+        format!(
+            "/admin/subscribers?search={}&amp;status={}&amp;page={page}",
+            urlencoding::encode(search),
+            urlencoding::encode(status)
+        )
+    };
+    let mut pagination = String::new();
+    if page > 1 {
+        write!(
+            &mut pagination,
+            r#"<a href="{}">Previous</a> "#,
+            page_url(page - 1)
+        )
+        .unwrap();
+    }
+    if total - offset > PAGE_SIZE {
+        write!(
+            &mut pagination,
+            r#"<a href="{}">Next</a>"#,
+            page_url(page + 1)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("subscribers.html"),
+            messages = messages,
+            search = escape_html(search),
+            status_options = status_options,
+            total = total,
+            rows = rows,
+            pagination = pagination
+        )))
+}
+
+/// Escape the characters `LIKE` treats as wildcards so the search matches them literally.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_like;
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"100%_sure\"), r"100\%\_sure\\");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+}
@@ -0,0 +1,21 @@
+mod actions;
+mod detail;
+mod export;
+mod import;
+mod list;
+mod tags;
+
+pub use actions::{delete_subscriber, mark_subscriber_confirmed, mark_subscriber_unsubscribed};
+pub use detail::get_subscriber;
+pub use export::export_subscribers;
+pub use import::{import_subscribers, import_subscribers_form};
+pub use list::get_subscribers;
+pub use tags::{remove_subscriber_tag, set_subscriber_tag};
+
+/// Every status a subscriber can be in.
+const STATUSES: [&str; 4] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+];
@@ -0,0 +1,106 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::is_valid_tag_key;
+use crate::util::{e500, escape_html, see_other};
+
+/// The longest value a tag can have.
+const MAX_VALUE_LENGTH: usize = 256;
+
+#[derive(serde::Deserialize)]
+pub struct TagForm {
+    key: String,
+    value: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RemoveTagForm {
+    key: String,
+}
+
+fn subscriber_url(subscriber_id: Uuid) -> String {
+    format!("/admin/subscribers/{subscriber_id}")
+}
+
+/// Set a tag on a subscriber, replacing its value if they already have it.
+#[tracing::instrument(name = "Set subscriber tag", skip(form, pool, _user_id))]
+pub async fn set_subscriber_tag(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<TagForm>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let TagForm { key, value } = form.0;
+    let (key, value) = (key.trim(), value.trim());
+    if !is_valid_tag_key(key) {
+        FlashMessage::error(
+            "Tag names can only contain letters, digits, '_', '-' and '.', and cannot be \
+            'and', 'or' or 'not'.",
+        )
+        .send();
+        return Ok(see_other(&subscriber_url(subscriber_id)));
+    }
+    if value.chars().count() > MAX_VALUE_LENGTH {
+        FlashMessage::error(format!(
+            "Tag values cannot be longer than {MAX_VALUE_LENGTH} characters."
+        ))
+        .send();
+        return Ok(see_other(&subscriber_url(subscriber_id)));
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags(subscriber_id, key, value)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (subscriber_id, key) DO UPDATE SET value = $3
+        "#,
+        subscriber_id,
+        key,
+        value
+    )
+    .execute(pool.as_ref())
+    .await;
+    match result {
+        Ok(_) => FlashMessage::info(format!("The tag {} has been set.", escape_html(key))).send(),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        Err(e) => {
+            return Err(e500(
+                anyhow::Error::from(e).context("Failed to store subscriber tag"),
+            ));
+        }
+    }
+    Ok(see_other(&subscriber_url(subscriber_id)))
+}
+
+#[tracing::instrument(name = "Remove subscriber tag", skip(form, pool, _user_id))]
+pub async fn remove_subscriber_tag(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<RemoveTagForm>,
+    pool: web::Data<PgPool>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND key = $2",
+        subscriber_id,
+        form.key
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to remove subscriber tag")
+    .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "The tag {} has been removed.",
+        escape_html(&form.key)
+    ))
+    .send();
+    Ok(see_other(&subscriber_url(subscriber_id)))
+}
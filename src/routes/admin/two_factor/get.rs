@@ -0,0 +1,79 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use qrcode::{QrCode, render::svg};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{Totp, UserId, count_recovery_codes, has_second_factor},
+    session_state::TypedSession,
+    util::{e500, escape_html, get_username},
+};
+
+/// Set up an authenticator or, once it is, manage the recovery codes or turn it off.
+///
+/// The secret being set up is kept in the session rather than the form, so that another site
+/// cannot make the user set up an authenticator it knows the secret of.
+pub async fn two_factor_form(
+    received: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut messages = String::new();
+    for msg in received.iter() {
+        writeln!(&mut messages, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    if has_second_factor(db_pool.as_ref(), *user_id)
+        .await
+        .map_err(e500)?
+    {
+        let remaining = count_recovery_codes(db_pool.as_ref(), *user_id)
+            .await
+            .map_err(e500)?;
+        return Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(format!(
+                include_str!("enabled.html"),
+                messages = messages,
+                remaining = remaining,
+            )));
+    }
+
+    let secret = match session.get_totp_secret_to_confirm().map_err(e500)? {
+        Some(secret) => secret,
+        None => {
+            let secret = Totp::generate_secret();
+            session
+                .insert_totp_secret_to_confirm(&secret)
+                .map_err(e500)?;
+            secret
+        }
+    };
+    let username = get_username(db_pool.as_ref(), *user_id)
+        .await
+        .map_err(e500)?;
+    let uri = Totp::new(&secret, &username).map_err(e500)?.uri();
+    let qr_code = QrCode::new(uri.as_bytes())
+        .context("Failed to encode the TOTP URI as a QR code")
+        .map_err(e500)?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("set_up.html"),
+            messages = messages,
+            qr_code = STANDARD.encode(qr_code),
+            uri = escape_html(&uri),
+            secret = secret.expose_secret(),
+        )))
+}
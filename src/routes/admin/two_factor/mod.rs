@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::two_factor_form;
+pub use post::{confirm_two_factor, regenerate_recovery_codes, turn_off_two_factor};
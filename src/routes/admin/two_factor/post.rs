@@ -0,0 +1,173 @@
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{
+        SecondFactor, Totp, UserId, disable_two_factor, enable_two_factor, get_session_version,
+        has_second_factor, log_out_everywhere, replace_recovery_codes, verify_second_factor,
+    },
+    clock::Clock,
+    session_state::TypedSession,
+    util::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+/// Turn on the authenticator being set up once the user shows it works with one of its codes.
+///
+/// Every other session of the user is logged out, as they were opened with the password alone.
+#[tracing::instrument(name = "Confirm two-factor authentication", skip_all, fields(user_id = %*user_id))]
+pub async fn confirm_two_factor(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+    clock: web::Data<Clock>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if has_second_factor(db_pool.as_ref(), *user_id)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(see_other("/admin/two_factor"));
+    }
+    let Some(secret) = session.get_totp_secret_to_confirm().map_err(e500)? else {
+        FlashMessage::error("The authenticator has expired, add the new one to your app.").send();
+        return Ok(see_other("/admin/two_factor"));
+    };
+    let Some(step) = Totp::new(&secret, "")
+        .map_err(e500)?
+        .verify(&form.code, clock.now())
+    else {
+        FlashMessage::error("The code you entered is not valid.").send();
+        return Ok(see_other("/admin/two_factor"));
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+    let codes = enable_two_factor(&mut transaction, *user_id, &secret, step)
+        .await
+        .map_err(e500)?;
+    log_out_everywhere(&mut *transaction, *user_id)
+        .await
+        .map_err(e500)?;
+    let session_version = get_session_version(&mut *transaction, *user_id)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit enabling two-factor authentication.")
+        .map_err(e500)?;
+
+    session.remove_totp_secret_to_confirm();
+    session.log_in(*user_id, session_version).map_err(e500)?;
+    Ok(recovery_codes_page(
+        "Two-factor authentication is on.",
+        &codes,
+    ))
+}
+
+/// Replace the recovery codes of the user, e.g. once they have used most of them.
+#[tracing::instrument(name = "Regenerate recovery codes", skip_all, fields(user_id = %*user_id))]
+pub async fn regenerate_recovery_codes(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    clock: web::Data<Clock>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if !check_code(&db_pool, *user_id, &form.code, &clock).await? {
+        return Ok(see_other("/admin/two_factor"));
+    }
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+    let codes = replace_recovery_codes(&mut transaction, *user_id)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit new recovery codes.")
+        .map_err(e500)?;
+
+    Ok(recovery_codes_page(
+        "Your previous recovery codes no longer work.",
+        &codes,
+    ))
+}
+
+/// Go back to logging in with the password alone.
+#[tracing::instrument(name = "Turn off two-factor authentication", skip_all, fields(user_id = %*user_id))]
+pub async fn turn_off_two_factor(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    clock: web::Data<Clock>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if !check_code(&db_pool, *user_id, &form.code, &clock).await? {
+        return Ok(see_other("/admin/two_factor"));
+    }
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+    disable_two_factor(&mut transaction, *user_id)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit disabling two-factor authentication.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Two-factor authentication is off.").send();
+    Ok(see_other("/admin/two_factor"))
+}
+
+/// Changes to the second factor need one, so that someone using a session left open cannot take
+/// it over.
+async fn check_code(
+    db_pool: &PgPool,
+    user_id: uuid::Uuid,
+    code: &str,
+    clock: &Clock,
+) -> Result<bool, actix_web::Error> {
+    let second_factor = verify_second_factor(db_pool, user_id, code, clock.now())
+        .await
+        .map_err(e500)?;
+    if second_factor == SecondFactor::Invalid {
+        FlashMessage::error("The code you entered is not valid.").send();
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn recovery_codes_page(message: &str, codes: &[String]) -> HttpResponse {
+    let codes: String = codes
+        .iter()
+        .map(|code| format!("<li><code>{code}</code></li>"))
+        .collect();
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("recovery_codes.html"),
+            message = message,
+            codes = codes,
+        ))
+}
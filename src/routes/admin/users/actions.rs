@@ -0,0 +1,106 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::util::{e500, escape_html, see_other};
+
+/// Stop a user from logging in, and log them out of the sessions they have open. Their account
+/// is kept so that it can be enabled again.
+#[tracing::instrument(name = "Disable user", skip(pool, current_user_id))]
+pub async fn disable_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_disabled(user_id.into_inner(), true, &pool, *current_user_id).await
+}
+
+#[tracing::instrument(name = "Enable user", skip(pool, current_user_id))]
+pub async fn enable_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_disabled(user_id.into_inner(), false, &pool, *current_user_id).await
+}
+
+async fn set_disabled(
+    user_id: Uuid,
+    disabled: bool,
+    pool: &PgPool,
+    current_user_id: UserId,
+) -> Result<HttpResponse, actix_web::Error> {
+    if disabled && user_id == *current_user_id {
+        FlashMessage::error("You cannot disable your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let user = sqlx::query!(
+        "UPDATE users SET disabled = $1 WHERE user_id = $2 RETURNING username",
+        disabled,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update user")
+    .map_err(e500)?;
+
+    let Some(user) = user else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let state = if disabled { "disabled" } else { "enabled" };
+    FlashMessage::info(format!("{} has been {state}.", escape_html(&user.username))).send();
+    Ok(see_other("/admin/users"))
+}
+
+/// Delete a user for good, along with the responses saved for their idempotent requests.
+///
+/// They are logged out of the sessions they have open on their next request.
+#[tracing::instrument(name = "Delete user", skip(pool, current_user_id))]
+pub async fn delete_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    current_user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if user_id == **current_user_id {
+        FlashMessage::error("You cannot delete your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+
+    sqlx::query!("DELETE FROM idempotency WHERE user_id = $1", user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the user's idempotency keys")
+        .map_err(e500)?;
+    let user = sqlx::query!(
+        "DELETE FROM users WHERE user_id = $1 RETURNING username",
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete user")
+    .map_err(e500)?;
+
+    let Some(user) = user else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit user deletion.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("{} has been deleted.", escape_html(&user.username))).send();
+    Ok(see_other("/admin/users"))
+}
@@ -0,0 +1,116 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::INVITATION_EXPIRY;
+use crate::authentication::{Role, UserId, generate_token, hash_token};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailTransport, SendEmailError};
+use crate::startup::ApplicationBaseUrl;
+use crate::util::{e500, escape_html, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    role: String,
+}
+
+/// Email someone a link to create their own account with the given role.
+///
+/// Inviting an address again sends a new link, and the previous one stops working.
+#[tracing::instrument(
+    name = "Invite user",
+    skip(form, pool, email_client, base_url, _user_id),
+    fields(email = %form.email, role = %form.role)
+)]
+pub async fn invite_user(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    _user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok(email) = SubscriberEmail::parse(form.0.email) else {
+        FlashMessage::error("The email address you entered is invalid.").send();
+        return Ok(see_other("/admin/users"));
+    };
+    let Ok(role) = Role::parse(&form.0.role) else {
+        FlashMessage::error("Choose one of the roles.").send();
+        return Ok(see_other("/admin/users"));
+    };
+
+    let existing_user = sqlx::query!(
+        "SELECT user_id FROM users WHERE lower(email) = lower($1)",
+        email.as_ref()
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to look up existing user")
+    .map_err(e500)?;
+    if existing_user.is_some() {
+        FlashMessage::error("This email address is already used by another user.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let token = generate_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (email, role, token_hash, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (email) DO UPDATE SET role = $2, token_hash = $3, created_at = now()
+        "#,
+        email.as_ref(),
+        role.as_str(),
+        hash_token(&token)
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to store invitation")
+    .map_err(e500)?;
+
+    send_invitation(email_client.as_ref(), &email, role, &base_url.0, &token)
+        .await
+        .context("Failed to send invitation email")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "An invitation has been sent to {}.",
+        escape_html(email.as_ref())
+    ))
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Send invitation email", skip(email_client, token))]
+async fn send_invitation(
+    email_client: &dyn EmailTransport,
+    email: &SubscriberEmail,
+    role: Role,
+    base_url: &str,
+    token: &str,
+) -> Result<(), SendEmailError> {
+    let invitation_link = format!("{base_url}/invitation?token={token}");
+    let days = INVITATION_EXPIRY.num_days();
+
+    let html_body = format!(
+        "You have been invited to help run the newsletter as {role}.<br/> \
+         <a href=\"{invitation_link}\">Choose your username and password</a> \
+         within {days} days to accept."
+    );
+    let plain_body = format!(
+        "You have been invited to help run the newsletter as {role}.\n\
+         Visit {invitation_link} within {days} days to choose your username and password."
+    );
+
+    email_client
+        .send_email(
+            email,
+            "You have been invited to the newsletter",
+            &html_body,
+            &plain_body,
+            &[],
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use super::INVITATION_EXPIRY;
+use crate::authentication::{CurrentUser, Role};
+use crate::util::{e500, escape_html};
+
+/// Every user who can log in or has been disabled, along with the invitations not accepted yet.
+pub async fn get_users(
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    user: web::ReqData<CurrentUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    let users = sqlx::query!(
+        "SELECT user_id, username, email, role, disabled FROM users ORDER BY username"
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch users")
+    .map_err(e500)?;
+
+    let mut user_rows = String::new();
+    for u in users {
+        let (status, toggle) = if u.disabled {
+            ("disabled", "enable")
+        } else {
+            ("active", "disable")
+        };
+        // Admins cannot lock themselves out.
+        let actions = if u.user_id == *user.user_id {
+            "(you)".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/users/{id}/{toggle}" method="post"><input type="submit" value="{toggle}"></form>
+                <form action="/admin/users/{id}/delete" method="post"><input type="submit" value="delete"></form>"#,
+                id = u.user_id
+            )
+        };
+        write!(
+            &mut user_rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{status}</td><td>{actions}</td></tr>",
+            escape_html(&u.username),
+            escape_html(u.email.as_deref().unwrap_or_default()),
+            u.role,
+        )
+        .unwrap();
+    }
+
+    let invitations = sqlx::query!(
+        "SELECT email, role, created_at FROM user_invitations ORDER BY created_at, email"
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch invitations")
+    .map_err(e500)?;
+
+    let mut invitation_rows = String::new();
+    for invitation in invitations {
+        let status = if Utc::now() - invitation.created_at > INVITATION_EXPIRY {
+            "expired, invite them again"
+        } else {
+            "pending"
+        };
+        write!(
+            &mut invitation_rows,
+            "<tr><td>{}</td><td>{}</td><td>{} UTC</td><td>{status}</td></tr>",
+            escape_html(&invitation.email),
+            invitation.role,
+            invitation.created_at.format("%Y-%m-%d %H:%M"),
+        )
+        .unwrap();
+    }
+
+    let mut roles = String::new();
+    for role in Role::ALL {
+        write!(&mut roles, r#"<option value="{role}">{role}</option>"#).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("users.html"),
+            messages = messages,
+            users = user_rows,
+            invitations = invitation_rows,
+            expiry_days = INVITATION_EXPIRY.num_days(),
+            roles = roles,
+        )))
+}
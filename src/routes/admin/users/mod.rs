@@ -0,0 +1,10 @@
+mod actions;
+mod invite;
+mod list;
+
+pub use actions::{delete_user, disable_user, enable_user};
+pub use invite::invite_user;
+pub use list::get_users;
+
+/// How long the link sent with an invitation can be used.
+pub const INVITATION_EXPIRY: chrono::TimeDelta = chrono::TimeDelta::days(7);
@@ -0,0 +1,190 @@
+use actix_web::{
+    HttpResponse,
+    http::header::ContentType,
+    web::{self, Query},
+};
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::SubscriptionSettings;
+use crate::util::e500;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+/// Confirm the subscription to the list a token was issued for.
+///
+/// The token is deleted in the same transaction that confirms the subscriber, so each link
+/// works exactly once.
+#[tracing::instrument(name = "Confirm a subscription", skip(parameters, pool, settings))]
+pub async fn confirm(
+    parameters: Query<Parameters>,
+    pool: web::Data<sqlx::PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")
+        .map_err(e500)?;
+
+    let Some(token) = get_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized()
+            .content_type(ContentType::html())
+            .body(include_str!("invalid_link.html")));
+    };
+
+    let expiry = TimeDelta::from_std(settings.confirmation_expiry()).unwrap_or(TimeDelta::MAX);
+    if Utc::now() - token.created_at > expiry {
+        return Ok(HttpResponse::Gone()
+            .content_type(ContentType::html())
+            .body(format!(
+                include_str!("link_expired.html"),
+                list_id = token.list_id
+            )));
+    }
+
+    delete_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .map_err(e500)?;
+
+    let page = if confirm_subscriber(&mut transaction, token.subscriber_id, token.list_id)
+        .await
+        .map_err(e500)?
+    {
+        include_str!("confirmed.html")
+    } else if is_confirmed(&mut transaction, token.subscriber_id, token.list_id)
+        .await
+        .map_err(e500)?
+    {
+        // Another link sent to the same subscriber was used first.
+        include_str!("already_confirmed.html")
+    } else {
+        // The subscriber has unsubscribed since the link was sent.
+        return Ok(HttpResponse::Unauthorized()
+            .content_type(ContentType::html())
+            .body(include_str!("invalid_link.html")));
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit subscription confirmation.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+/// Move a subscriber's subscription to a list from `pending_confirmation` to `confirmed`.
+///
+/// The subscriber is confirmed as well the first time they confirm a subscription to any list.
+/// Returns whether the subscription was pending confirmation.
+#[tracing::instrument(name = "Confirm subscriber", skip(transaction))]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let confirmed = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND list_id = $2 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to confirm list subscription")?
+    .rows_affected();
+    if confirmed == 0 {
+        return Ok(false);
+    }
+
+    // A subscriber who unsubscribed since the link was sent has to subscribe again.
+    let confirmed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status <> 'unsubscribed'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to confirm subscriber")?
+    .rows_affected();
+    Ok(confirmed == 1)
+}
+
+async fn is_confirmed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let subscription = sqlx::query!(
+        r#"
+        SELECT s.status, l.status AS list_status
+        FROM subscriptions s
+        JOIN list_subscriptions l ON l.subscriber_id = s.id
+        WHERE s.id = $1 AND l.list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to fetch subscriber status")?;
+    Ok(subscription.status == "confirmed" && subscription.list_status == "confirmed")
+}
+
+/// The token row stays locked until the transaction ends, so the same link being followed
+/// twice at once confirms the subscriber only once.
+#[tracing::instrument(name = "Get subscription token", skip(transaction, token))]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+) -> Result<Option<SubscriptionToken>, anyhow::Error> {
+    let token = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscriber_id, list_id, created_at FROM subscription_tokens
+        WHERE subscription_token = $1
+        FOR UPDATE
+        "#,
+        token
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch subscription token")?;
+    Ok(token)
+}
+
+#[tracing::instrument(name = "Delete subscription token", skip(transaction, token))]
+async fn delete_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscription_token = $1",
+        token
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete subscription token")?;
+    Ok(())
+}
@@ -1,7 +1,12 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpResponse, web};
+use sqlx::PgPool;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
+use crate::lists::{get_lists, list_options};
+use crate::util::e500;
+
+pub async fn home(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(pool.as_ref()).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
         .content_type(actix_web::http::header::ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(include_str!("home.html"), list_options(&lists))))
 }
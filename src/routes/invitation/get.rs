@@ -0,0 +1,37 @@
+use std::fmt::Write;
+
+use actix_web::{
+    HttpResponse,
+    http::header::ContentType,
+    web::{self, Query},
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+
+use super::{InvitationError, Parameters, get_invitation};
+use crate::util::escape_html;
+
+/// Where the link sent with an invitation leads, to choose a username and password.
+pub async fn accept_invitation_form(
+    parameters: Query<Parameters>,
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InvitationError> {
+    let invitation = get_invitation(pool.as_ref(), &parameters.token).await?;
+
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("invitation.html"),
+            messages = messages,
+            role = invitation.role,
+            email = escape_html(&invitation.email),
+            token = escape_html(&parameters.token),
+        )))
+}
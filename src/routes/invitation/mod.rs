@@ -0,0 +1,94 @@
+mod get;
+mod post;
+
+pub use get::accept_invitation_form;
+pub use post::accept_invitation;
+
+use std::fmt::{self, Debug};
+
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, http::header::ContentType};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgExecutor;
+
+use crate::authentication::{Role, hash_token};
+use crate::routes::admin::INVITATION_EXPIRY;
+use crate::routes::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+struct Invitation {
+    email: String,
+    role: Role,
+}
+
+/// Look up the invitation a link was sent for.
+///
+/// Within a transaction, the invitation stays locked until it ends, so the same link being used
+/// twice at once creates a single user.
+#[tracing::instrument(name = "Get invitation", skip_all)]
+async fn get_invitation(
+    executor: impl PgExecutor<'_>,
+    token: &str,
+) -> Result<Invitation, InvitationError> {
+    let invitation = sqlx::query!(
+        r#"
+        SELECT email, role, created_at FROM user_invitations
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch invitation")?
+    .ok_or(InvitationError::InvalidLink)?;
+
+    if Utc::now() - invitation.created_at > INVITATION_EXPIRY {
+        return Err(InvitationError::Expired);
+    }
+    Ok(Invitation {
+        email: invitation.email,
+        role: Role::parse(&invitation.role).map_err(anyhow::Error::msg)?,
+    })
+}
+
+#[derive(thiserror::Error)]
+pub enum InvitationError {
+    #[error("The invitation does not exist or has been used")]
+    InvalidLink,
+    #[error("The invitation has expired")]
+    Expired,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for InvitationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for InvitationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::Expired => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let page = match self {
+            Self::InvalidLink => include_str!("invalid_link.html"),
+            Self::Expired => include_str!("link_expired.html"),
+            Self::UnexpectedError(_) => return HttpResponse::InternalServerError().finish(),
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(page)
+    }
+}
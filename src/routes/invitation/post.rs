@@ -0,0 +1,109 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{InvitationError, get_invitation};
+use crate::authentication::compute_password_hash;
+use crate::routes::admin::validate_password_strength;
+use crate::telemetry::spawn_blocking_with_async;
+use crate::util::see_other;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: String,
+    username: String,
+    password: Secret<String>,
+    confirm_password: Secret<String>,
+}
+
+/// Create the account an invitation was sent for. The invitation is deleted along with it, so
+/// each link works exactly once.
+#[tracing::instrument(
+    name = "Accept invitation",
+    skip(form, pool),
+    fields(username = %form.username, user_id = tracing::field::Empty)
+)]
+pub async fn accept_invitation(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InvitationError> {
+    let form = form.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")?;
+    let invitation = get_invitation(&mut *transaction, &form.token).await?;
+
+    // The token has just been checked, so it is safe to put back in a url.
+    let form_url = format!("/invitation?token={}", form.token);
+    let username = form.username.trim();
+    if username.is_empty() {
+        FlashMessage::error("Choose a username.").send();
+        return Ok(see_other(&form_url));
+    }
+    if form.password.expose_secret() != form.confirm_password.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other(&form_url));
+    }
+    if let Err(e) = validate_password_strength(&form.password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_url));
+    }
+
+    let password = form.password;
+    let password_hash = spawn_blocking_with_async(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task")?
+        .context("Failed to hash password")?;
+
+    let user_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        invitation.email,
+        invitation.role.as_str()
+    )
+    .execute(&mut *transaction)
+    .await;
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("users_username_key") => {
+            FlashMessage::error("This username is already taken.").send();
+            return Ok(see_other(&form_url));
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            FlashMessage::error("This email address is already used by another user.").send();
+            return Ok(see_other(&form_url));
+        }
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context("Failed to store user")
+                .into());
+        }
+    }
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    sqlx::query!(
+        "DELETE FROM user_invitations WHERE email = $1",
+        invitation.email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete invitation")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit new user.")?;
+
+    FlashMessage::info("Your account has been created, you can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,88 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::TemplateValues;
+use crate::issue_delivery_workers::render_content;
+use crate::startup::ApplicationBaseUrl;
+use crate::util::{e500, escape_html};
+
+pub async fn issue_archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published'
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to fetch published newsletter issues")
+    .map_err(e500)?;
+
+    let mut items = String::new();
+    for issue in issues {
+        write!(
+            &mut items,
+            r#"<li><a href="/issues/{}">{}</a> ({})</li>"#,
+            issue.newsletter_issue_id,
+            escape_html(&issue.title),
+            issue.published_at.format("%Y-%m-%d")
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("issues.html"), items)))
+}
+
+/// The web view of a published issue, which delivered emails link to.
+pub async fn view_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'published'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to fetch newsletter issue")
+    .map_err(e500)?;
+
+    let Some(issue) = issue else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    // The page is public, so only the variables which are not about a subscriber are filled in.
+    let view_in_browser_url = format!("{}/issues/{newsletter_issue_id}", base_url.0);
+    let values = TemplateValues {
+        view_in_browser_url: Some(&view_in_browser_url),
+        ..Default::default()
+    };
+    let html_content = render_content(&issue.html_content, |t| t.render_html(&values));
+
+    // Issues written as a full document are served as is, fragments get a minimal page around them.
+    let body = if html_content.contains("<html") {
+        html_content
+    } else {
+        format!(
+            include_str!("issue.html"),
+            escape_html(&issue.title),
+            html_content
+        )
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
@@ -0,0 +1,171 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::RESET_EXPIRY;
+use crate::authentication::{generate_token, hash_token};
+use crate::clock::Clock;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailTransport;
+use crate::startup::ApplicationBaseUrl;
+use crate::util::{e500, escape_html, see_other};
+
+pub async fn forgot_password_form(received: IncomingFlashMessages) -> HttpResponse {
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("forgot.html"), messages))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username_or_email: String,
+}
+
+struct User {
+    user_id: Uuid,
+    username: String,
+    email: String,
+}
+
+/// Email a link to reset their password to the user with the given username or email address.
+///
+/// The response is the same whether there is such a user or not, so that it cannot be used to
+/// find out who has an account.
+#[tracing::instrument(name = "Forgot password", skip_all)]
+pub async fn forgot_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = find_user(&pool, form.0.username_or_email.trim())
+        .await
+        .map_err(e500)?;
+
+    if let Some(user) = user {
+        let now = clock.now();
+        delete_expired_tokens(&pool, now).await.map_err(e500)?;
+        let token = generate_token();
+        sqlx::query!(
+            r#"
+            INSERT INTO password_reset_tokens (token_hash, user_id, created_at)
+            VALUES ($1, $2, $3)
+            "#,
+            hash_token(&token),
+            user.user_id,
+            now
+        )
+        .execute(pool.as_ref())
+        .await
+        .context("Failed to store password reset token")
+        .map_err(e500)?;
+
+        // Sent in the background, so that the response does not take longer when there is a user.
+        tokio::spawn(
+            send_reset_email(email_client.into_inner(), user, base_url.0.clone(), token)
+                .in_current_span(),
+        );
+    }
+
+    FlashMessage::info(
+        "If there is an account with this username or email address, \
+         a link to reset its password has been sent to its email address.",
+    )
+    .send();
+    Ok(see_other("/login"))
+}
+
+/// Disabled users cannot log in even with a new password, and users without an email address
+/// cannot be sent a link.
+#[tracing::instrument(name = "Find user to reset the password of", skip(pool))]
+async fn find_user(pool: &PgPool, username_or_email: &str) -> Result<Option<User>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT user_id, username, email AS "email!" FROM users
+        WHERE (username = $1 OR lower(email) = lower($1))
+            AND email IS NOT NULL AND NOT disabled
+        ORDER BY username = $1 DESC
+        LIMIT 1
+        "#,
+        username_or_email
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up user")?;
+    Ok(user)
+}
+
+/// Links are only ever sent on request, so the tokens that can no longer be used are cleaned up
+/// whenever a new one is issued.
+#[tracing::instrument(name = "Delete expired password reset tokens", skip(pool))]
+async fn delete_expired_tokens(pool: &PgPool, now: DateTime<Utc>) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE created_at < $1",
+        now - RESET_EXPIRY
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired password reset tokens")?;
+    Ok(())
+}
+
+async fn send_reset_email(
+    email_client: Arc<dyn EmailTransport>,
+    user: User,
+    base_url: String,
+    token: String,
+) {
+    let recipient = match SubscriberEmail::parse(user.email) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::error!(error.message = %e, "The user has an invalid email address");
+            return;
+        }
+    };
+    let reset_link = format!("{base_url}/login/reset?token={token}");
+    let minutes = RESET_EXPIRY.num_minutes();
+
+    let html_body = format!(
+        "Someone asked to reset the password of your account {}.<br/> \
+         <a href=\"{reset_link}\">Choose a new password</a> within {minutes} minutes.<br/> \
+         If it was not you, you can ignore this email.",
+        escape_html(&user.username)
+    );
+    let plain_body = format!(
+        "Someone asked to reset the password of your account {}.\n\
+         Visit {reset_link} within {minutes} minutes to choose a new password.\n\
+         If it was not you, you can ignore this email.",
+        user.username
+    );
+
+    if let Err(e) = email_client
+        .send_email(
+            &recipient,
+            "Reset your password",
+            &html_body,
+            &plain_body,
+            &[],
+        )
+        .await
+    {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send password reset email"
+        );
+    }
+}
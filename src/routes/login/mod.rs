@@ -1,4 +1,13 @@
+pub mod forgot;
 pub mod get;
 pub mod post;
+pub mod reset;
+pub mod two_factor;
+pub use forgot::{forgot_password, forgot_password_form};
 pub use get::login_form;
 pub use post::login;
+pub use reset::{reset_password, reset_password_form};
+pub use two_factor::{second_factor, second_factor_form};
+
+/// How long the link sent to reset a password can be used.
+pub const RESET_EXPIRY: chrono::TimeDelta = chrono::TimeDelta::hours(1);
@@ -1,4 +1,4 @@
-use crate::authentication::{AuthError, Credentials};
+use crate::authentication::{AuthError, Credentials, get_session_version, has_second_factor};
 use crate::routes::error_chain_fmt;
 use crate::session_state::TypedSession;
 use crate::util::see_other;
@@ -23,9 +23,22 @@ pub async fn login(
     match crate::authentication::validate_credentials(&pg_pool, credentials).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", tracing::field::display(&user_id));
-            session.renew();
+            // Users with an authenticator are only half logged in until they enter a code.
+            let has_totp = has_second_factor(pg_pool.as_ref(), user_id)
+                .await
+                .map_err(|e| login_err(LoginError::UnknownError(e)))?;
+            if has_totp {
+                session
+                    .insert_half_authenticated_user_id(user_id)
+                    .map_err(|e| login_err(LoginError::UnknownError(e.into())))?;
+                return Ok(see_other("/login/two_factor"));
+            }
+
+            let session_version = get_session_version(pg_pool.as_ref(), user_id)
+                .await
+                .map_err(|e| login_err(LoginError::UnknownError(e)))?;
             session
-                .insert_user_id(user_id)
+                .log_in(user_id, session_version)
                 .map_err(|e| login_err(LoginError::UnknownError(e.into())))?;
             Ok(see_other("/admin/dashboard"))
         }
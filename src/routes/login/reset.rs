@@ -0,0 +1,172 @@
+use std::fmt::{self, Debug, Write};
+
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{StatusCode, header::ContentType},
+    web::{self, Query},
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::RESET_EXPIRY;
+use crate::authentication::{change_password, hash_token, log_out_everywhere};
+use crate::clock::Clock;
+use crate::routes::admin::validate_password_strength;
+use crate::routes::error_chain_fmt;
+use crate::util::{escape_html, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+/// Where the link sent to reset a password leads, to choose a new one.
+pub async fn reset_password_form(
+    parameters: Query<Parameters>,
+    received: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, ResetError> {
+    get_user_id(pool.as_ref(), &parameters.token, clock.now()).await?;
+
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("reset.html"),
+            messages = messages,
+            token = escape_html(&parameters.token),
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: String,
+    new_password: Secret<String>,
+    confirm_password: Secret<String>,
+}
+
+/// Set the new password and log the user out of every session they have open, in case someone
+/// else got hold of the old one.
+///
+/// Every link sent to the user stops working, so each can be used at most once.
+#[tracing::instrument(name = "Reset password", skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn reset_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, ResetError> {
+    let form = form.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")?;
+    let user_id = get_user_id(&mut *transaction, &form.token, clock.now()).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    // The token has just been checked, so it is safe to put back in a url.
+    let form_url = format!("/login/reset?token={}", form.token);
+    if form.new_password.expose_secret() != form.confirm_password.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&form_url));
+    }
+    if let Err(e) = validate_password_strength(&form.new_password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_url));
+    }
+
+    change_password(user_id, form.new_password, &mut *transaction).await?;
+    log_out_everywhere(&mut *transaction, user_id).await?;
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete password reset tokens")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit password reset.")?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+/// The user a link to reset a password was sent to.
+///
+/// Within a transaction, the token stays locked until it ends, so the same link being used twice
+/// at once resets the password once.
+#[tracing::instrument(name = "Get password reset token", skip_all)]
+async fn get_user_id(
+    executor: impl PgExecutor<'_>,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<Uuid, ResetError> {
+    let token = sqlx::query!(
+        r#"
+        SELECT user_id, created_at FROM password_reset_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch password reset token")?
+    .ok_or(ResetError::InvalidLink)?;
+
+    if now - token.created_at > RESET_EXPIRY {
+        return Err(ResetError::Expired);
+    }
+    Ok(token.user_id)
+}
+
+#[derive(thiserror::Error)]
+pub enum ResetError {
+    #[error("The password reset token does not exist or has been used")]
+    InvalidLink,
+    #[error("The password reset token has expired")]
+    Expired,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for ResetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::Expired => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let page = match self {
+            Self::InvalidLink => include_str!("reset_invalid_link.html"),
+            Self::Expired => include_str!("reset_link_expired.html"),
+            Self::UnexpectedError(_) => return HttpResponse::InternalServerError().finish(),
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(page)
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+
+use crate::authentication::{SecondFactor, get_session_version, verify_second_factor};
+use crate::clock::Clock;
+use crate::session_state::TypedSession;
+use crate::util::{e500, see_other};
+
+/// How many invalid codes can be entered after a password before it has to be entered again.
+const MAX_FAILED_SECOND_FACTORS: u32 = 5;
+
+/// The second step of logging in, for users with an authenticator.
+pub async fn second_factor_form(
+    received: IncomingFlashMessages,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session
+        .get_half_authenticated_user_id()
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(see_other("/login"));
+    }
+
+    let mut messages = String::new();
+    for msg in received.iter() {
+        // This should never throw an error
+        write!(&mut messages, "{}", msg.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("two_factor.html"), messages)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+/// Log in the user who entered their password with a code from their authenticator or one of
+/// their recovery codes.
+#[tracing::instrument(name = "Second factor", skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn second_factor(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_half_authenticated_user_id().map_err(e500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    match verify_second_factor(pool.as_ref(), user_id, &form.code, clock.now())
+        .await
+        .map_err(e500)?
+    {
+        SecondFactor::Totp => {}
+        SecondFactor::RecoveryCode { remaining } => FlashMessage::warning(format!(
+            "You logged in with a recovery code, {remaining} left."
+        ))
+        .send(),
+        SecondFactor::Invalid => {
+            let failures = session.record_failed_second_factor().map_err(e500)?;
+            if failures >= MAX_FAILED_SECOND_FACTORS {
+                session.log_out();
+                FlashMessage::error("Too many invalid codes, log in again.").send();
+                return Ok(see_other("/login"));
+            }
+            FlashMessage::error("The code you entered is not valid.").send();
+            return Ok(see_other("/login/two_factor"));
+        }
+    }
+
+    let session_version = get_session_version(pool.as_ref(), user_id)
+        .await
+        .map_err(e500)?;
+    session.log_in(user_id, session_version).map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
@@ -2,12 +2,25 @@ pub mod admin;
 pub mod confirm_subscription;
 pub mod health_check;
 pub mod home;
+pub mod invitation;
+pub mod issues;
 pub mod login;
 pub mod subscription;
+pub mod tracking;
+pub mod unsubscribe;
+pub mod webhooks;
 
 pub use admin::{admin_dashboard, change_password, change_password_form, log_out};
 pub use confirm_subscription::*;
 pub use health_check::*;
 pub use home::*;
-pub use login::{login, login_form};
+pub use invitation::{accept_invitation, accept_invitation_form};
+pub use issues::{issue_archive, view_issue};
+pub use login::{
+    forgot_password, forgot_password_form, login, login_form, reset_password, reset_password_form,
+    second_factor, second_factor_form,
+};
 pub use subscription::*;
+pub use tracking::{track_click, track_open};
+pub use unsubscribe::{unsubscribe, unsubscribe_form, unsubscribe_from_all_lists};
+pub use webhooks::email_webhook;
@@ -8,14 +8,16 @@ use uuid::Uuid;
 
 use actix_web::{
     HttpResponse, ResponseError,
-    http::StatusCode,
+    http::{StatusCode, header::ContentType},
     web::{self, Form},
 };
 
 use crate::Subscription;
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailTransport, SendEmailError};
+use crate::lists::{MailingList, get_default_list, get_list};
+use crate::util::escape_html;
 
-fn get_subscription_token() -> String {
+pub(crate) fn get_subscription_token() -> String {
     let mut rng = rand::thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -34,9 +36,10 @@ fn get_subscription_token() -> String {
 pub async fn subscription(
     Form(form): Form<Subscription>,
     db: web::Data<sqlx::PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailTransport>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let list_id = form.list_id;
     let sub = form.try_into().map_err(SubscribeError::ValidationError)?;
 
     let mut transaction = db
@@ -45,37 +48,198 @@ pub async fn subscription(
         .await
         .context("Failed to get Postgres connection from Pool.")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &sub)
-        .await
-        .context("Failed to insert new subscriber into database.")?;
+    let list = match list_id {
+        Some(list_id) => get_list(&mut *transaction, list_id)
+            .await?
+            .ok_or_else(|| SubscribeError::ValidationError("The list does not exist".into()))?,
+        None => get_default_list(&mut *transaction).await?,
+    };
+
+    // Whatever state the address is in, the response is the same so that it cannot be used to
+    // find out who is subscribed. The difference only shows in the email we send.
+    let subscriber = get_or_insert_subscriber(&mut transaction, &sub).await?;
+    let subscriber_id = match subscriber {
+        StoredSubscriber::Existing(subscriber) if subscriber.status == "confirmed" => {
+            let list_status = get_list_status(&mut transaction, list.list_id, subscriber.id)
+                .await
+                .context("Failed to look up list subscription in database.")?;
+            if list_status.as_deref() == Some("confirmed") {
+                transaction
+                    .rollback()
+                    .await
+                    .context("Failed to roll back transaction.")?;
+                send_already_subscribed_email(email_client.as_ref(), sub, &list, &base_url.0)
+                    .await
+                    .context("Failed to send notice to existing subscriber.")?;
+                return Ok(HttpResponse::Ok().finish());
+            }
+            subscriber.id
+        }
+        // Someone who never confirmed or has since unsubscribed gets a fresh link.
+        StoredSubscriber::Existing(subscriber) => {
+            mark_as_pending(&mut transaction, subscriber.id)
+                .await
+                .context("Failed to update existing subscriber in database.")?;
+            subscriber.id
+        }
+        StoredSubscriber::New(subscriber_id) => subscriber_id,
+    };
+
+    subscribe_to_list(
+        &mut transaction,
+        list.list_id,
+        subscriber_id,
+        "pending_confirmation",
+    )
+    .await
+    .context("Failed to add subscriber to list.")?;
 
     let token = get_subscription_token();
 
-    store_token(&mut transaction, subscriber_id, &token)
+    store_token(&mut transaction, subscriber_id, list.list_id, &token)
         .await
-        .context("Failed to store confirmation token into database")?;
+        .map_err(|e| {
+            SubscribeError::from_db_error(e, "Failed to store confirmation token into database")
+        })?;
 
-    transaction
-        .commit()
-        .await
-        .context("Failed to commit new subscriber into database.")?;
+    transaction.commit().await.map_err(|e| {
+        SubscribeError::from_db_error(e, "Failed to commit new subscriber into database.")
+    })?;
 
-    send_email(&email_client, sub, &base_url.0, &token)
+    send_email(email_client.as_ref(), sub, &list, &base_url.0, &token)
         .await
         .context("Failed to send confirmation email to new subscriber.")?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+enum StoredSubscriber {
+    New(Uuid),
+    Existing(ExistingSubscriber),
+}
+
+/// The subscriber with the address of the form, who is inserted if there is none yet.
+///
+/// When another request inserts the same address at the same time, this one carries on with the
+/// subscriber it inserted, so that both get the same response.
+async fn get_or_insert_subscriber(
+    db: &mut sqlx::Transaction<'_, Postgres>,
+    form: &NewSubscriber,
+) -> Result<StoredSubscriber, anyhow::Error> {
+    if let Some(subscriber) = get_existing_subscriber(db, form)
+        .await
+        .context("Failed to look up existing subscriber in database.")?
+    {
+        return Ok(StoredSubscriber::Existing(subscriber));
+    }
+    if let Some(subscriber_id) = insert_subscriber(db, form)
+        .await
+        .context("Failed to insert new subscriber into database.")?
+    {
+        return Ok(StoredSubscriber::New(subscriber_id));
+    }
+    let subscriber = get_existing_subscriber(db, form)
+        .await
+        .context("Failed to look up existing subscriber in database.")?
+        .context("The subscriber inserted by another request does not exist.")?;
+    Ok(StoredSubscriber::Existing(subscriber))
+}
+
+#[tracing::instrument(name = "Get existing subscriber from database", skip(db, form))]
+async fn get_existing_subscriber(
+    db: &mut sqlx::Transaction<'_, Postgres>,
+    form: &NewSubscriber,
+) -> Result<Option<ExistingSubscriber>, sqlx::error::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        "SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE",
+        form.email.as_ref()
+    )
+    .fetch_optional(&mut **db)
+    .await
+    .inspect_err(|e| {
+        tracing::error!("Failed to fetch subscriber from database: {e:?}");
+    })
+}
+
+#[tracing::instrument(name = "Mark subscriber as pending confirmation", skip(db))]
+async fn mark_as_pending(
+    db: &mut sqlx::Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::error::Error> {
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'pending_confirmation' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(&mut **db)
+    .await
+    .inspect_err(|e| {
+        tracing::error!("Failed to update subscriber in database: {e:?}");
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get list subscription from database", skip(db))]
+async fn get_list_status(
+    db: &mut sqlx::Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::error::Error> {
+    let list_subscription = sqlx::query!(
+        r#"SELECT status FROM list_subscriptions
+                 WHERE list_id = $1 AND subscriber_id = $2
+                 FOR UPDATE"#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(&mut **db)
+    .await
+    .inspect_err(|e| {
+        tracing::error!("Failed to fetch list subscription from database: {e:?}");
+    })?;
+    Ok(list_subscription.map(|s| s.status))
+}
+
+/// Add a subscriber to a list, or change their status on it if they already are.
+#[tracing::instrument(name = "Save list subscription to database", skip(db))]
+pub(crate) async fn subscribe_to_list(
+    db: &mut sqlx::Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    status: &str,
+) -> Result<(), sqlx::error::Error> {
+    sqlx::query!(
+        r#"INSERT INTO list_subscriptions(list_id, subscriber_id, status, subscribed_at)
+                 VALUES($1, $2, $3, now())
+                 ON CONFLICT (list_id, subscriber_id) DO UPDATE SET status = $3"#,
+        list_id,
+        subscriber_id,
+        status
+    )
+    .execute(&mut **db)
+    .await
+    .inspect_err(|e| {
+        tracing::error!("Failed to save list subscription to database: {e:?}");
+    })?;
+    Ok(())
+}
+
+/// Returns `None` if a subscriber with the same address was inserted first.
 #[tracing::instrument(name = "Save new subscriber to database", skip(db, form))]
 async fn insert_subscriber(
     db: &mut sqlx::Transaction<'_, Postgres>,
     form: &NewSubscriber,
-) -> Result<Uuid, sqlx::error::Error> {
+) -> Result<Option<Uuid>, sqlx::error::Error> {
     let uuid = Uuid::new_v4();
-    sqlx::query!(
+    let inserted = sqlx::query!(
         r#"INSERT INTO subscriptions(id,email,name,subscribed_at, status)
-                 VALUES($1, $2, $3,$4, 'pending_confirmation')"#,
+                 VALUES($1, $2, $3,$4, 'pending_confirmation')
+                 ON CONFLICT (email) DO NOTHING"#,
         uuid,
         form.email.as_ref(),
         form.name.as_ref(),
@@ -86,43 +250,85 @@ async fn insert_subscriber(
     .inspect_err(|e| {
         tracing::error!("Failed to insert subscriber to database: {e:?}");
     })?;
-    Ok(uuid)
+    Ok((inserted.rows_affected() == 1).then_some(uuid))
 }
 
 #[tracing::instrument(
     name = "Send confirmation email to new subscriber",
-    skip(email_client, sub)
+    skip(email_client, sub, list)
 )]
-async fn send_email(
-    email_client: &EmailClient,
+pub(crate) async fn send_email(
+    email_client: &dyn EmailTransport,
     sub: NewSubscriber,
+    list: &MailingList,
     base_url: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let confirmation_link = format!("{base_url}/subscription/confirm?subscription_token={token}");
 
     let html_body = format!(
-        "welcome to our newsletter!<br/> \
-         Click <a href=\"{confirmation_link}\">"
+        "welcome to {}!<br/> \
+         Click <a href=\"{confirmation_link}\">",
+        escape_html(&list.name)
     );
     let plain_body = format!(
-        "welcome to our newsletter!\nVisit {confirmation_link} to confirm your subscription"
+        "welcome to {}!\nVisit {confirmation_link} to confirm your subscription",
+        list.name
     );
 
     email_client
-        .send_email(&sub.email, "welcome!", &html_body, &plain_body)
-        .await
+        .send_email(&sub.email, "welcome!", &html_body, &plain_body, &[])
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send notice to existing subscriber",
+    skip(email_client, sub, list)
+)]
+async fn send_already_subscribed_email(
+    email_client: &dyn EmailTransport,
+    sub: NewSubscriber,
+    list: &MailingList,
+    base_url: &str,
+) -> Result<(), SendEmailError> {
+    let html_body = format!(
+        "You are already subscribed to {}!<br/> \
+         Past issues are available <a href=\"{base_url}/issues\">here</a>.<br/> \
+         If you did not just try to subscribe again, you can ignore this email.",
+        escape_html(&list.name)
+    );
+    let plain_body = format!(
+        "You are already subscribed to {}!\n\
+         Past issues are available at {base_url}/issues\n\
+         If you did not just try to subscribe again, you can ignore this email.",
+        list.name
+    );
+
+    email_client
+        .send_email(
+            &sub.email,
+            "You are already subscribed",
+            &html_body,
+            &plain_body,
+            &[],
+        )
+        .await?;
+    Ok(())
 }
 
 #[tracing::instrument(name = "Store subscription token in the database", skip(token))]
-async fn store_token(
+pub(crate) async fn store_token(
     pool: &mut sqlx::Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     token: &str,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        "INSERT INTO subscription_tokens(subscriber_id, subscription_token) VALUES($1, $2)",
+        r#"INSERT INTO subscription_tokens(subscriber_id, list_id, subscription_token)
+                 VALUES($1, $2, $3)"#,
         subscriber_id,
+        list_id,
         token
     )
     .execute(&mut **pool)
@@ -135,10 +341,24 @@ async fn store_token(
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    /// Another request changed the same subscription at the same time.
+    #[error("The subscription was changed by another request")]
+    ConflictingSubscription(#[source] sqlx::Error),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl SubscribeError {
+    fn from_db_error(e: sqlx::Error, context: &'static str) -> Self {
+        match e {
+            sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+                Self::ConflictingSubscription(e)
+            }
+            e => Self::UnexpectedError(anyhow::Error::new(e).context(context)),
+        }
+    }
+}
+
 impl Debug for SubscribeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         error_chain_fmt(self, f)
@@ -147,10 +367,21 @@ impl Debug for SubscribeError {
 
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
-        if let Self::ValidationError(_) = self {
-            StatusCode::BAD_REQUEST
-        } else {
-            StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            // Answered like a successful subscription, as anything else would tell that the
+            // address is known.
+            Self::ConflictingSubscription(_) => StatusCode::OK,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::ConflictingSubscription(_) => HttpResponse::Ok().finish(),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
         }
     }
 }
@@ -0,0 +1,84 @@
+use actix_web::{
+    HttpResponse,
+    http::header::{CacheControl, CacheDirective, LOCATION},
+    web,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::domain::{TrackedDelivery, TrackingToken};
+use crate::startup::HmacSecret;
+use crate::util::e400;
+
+/// A transparent 1x1 gif.
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+/// The pixel of an issue tracking opens, loaded when a subscriber displays it.
+#[tracing::instrument(name = "Track an open", skip_all)]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let delivery = TrackingToken::parse(&token, &hmac_secret.0).map_err(e400)?;
+    if delivery.url.is_some() {
+        return Err(e400("Not an open tracking token"));
+    }
+    // Failing to record an event is not the subscriber's problem.
+    if let Err(e) = record_event(&pool, &delivery).await {
+        tracing::warn!(error.cause_chain = ?e, "Failed to record an open");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every time the issue is displayed counts.
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL))
+}
+
+/// Where the tracked links of an issue lead, before sending the subscriber to the actual link.
+#[tracing::instrument(name = "Track a click", skip_all)]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let delivery = TrackingToken::parse(&token, &hmac_secret.0).map_err(e400)?;
+    let Some(url) = &delivery.url else {
+        return Err(e400("Not a click tracking token"));
+    };
+    if let Err(e) = record_event(&pool, &delivery).await {
+        tracing::warn!(error.cause_chain = ?e, "Failed to record a click");
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url.as_str()))
+        .finish())
+}
+
+/// Record an open, or a click if the token was for a link.
+///
+/// Nothing is recorded for an issue or subscriber which has since been deleted.
+async fn record_event(pool: &PgPool, delivery: &TrackedDelivery) -> Result<(), anyhow::Error> {
+    let kind = if delivery.url.is_some() {
+        "click"
+    } else {
+        "open"
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_tracking_events (newsletter_issue_id, subscriber_id, kind, url)
+        SELECT i.newsletter_issue_id, s.id, $3, $4
+        FROM newsletter_issues i, subscriptions s
+        WHERE i.newsletter_issue_id = $1 AND s.id = $2
+        "#,
+        delivery.newsletter_issue_id,
+        delivery.subscriber_id,
+        kind,
+        delivery.url
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a tracking event")?;
+    Ok(())
+}
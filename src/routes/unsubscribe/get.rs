@@ -0,0 +1,20 @@
+use actix_web::{
+    HttpResponse,
+    http::header::ContentType,
+    web::{Data, Query},
+};
+
+use crate::{domain::UnsubscribeToken, startup::HmacSecret, util::e400};
+
+use super::Parameters;
+
+pub async fn unsubscribe_form(
+    parameters: Query<Parameters>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    UnsubscribeToken::parse(&parameters.token, &hmac_secret.0).map_err(e400)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(include_str!("unsubscribe.html"), parameters.token)))
+}
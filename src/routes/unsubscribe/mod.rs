@@ -0,0 +1,10 @@
+mod get;
+mod post;
+
+pub use get::unsubscribe_form;
+pub use post::{unsubscribe, unsubscribe_from_all_lists};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
@@ -0,0 +1,73 @@
+use actix_web::{
+    HttpResponse,
+    http::header::ContentType,
+    web::{Data, Query},
+};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::UnsubscribeToken,
+    startup::HmacSecret,
+    util::{e400, e500},
+};
+
+use super::Parameters;
+
+/// Handles both the form on the confirmation page and one-click unsubscribe requests (RFC 8058)
+/// sent by mail clients through the `List-Unsubscribe-Post` header.
+#[tracing::instrument(name = "Unsubscribe", skip_all, fields(subscriber_id=tracing::field::Empty))]
+pub async fn unsubscribe(
+    parameters: Query<Parameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = UnsubscribeToken::parse(&parameters.token, &hmac_secret.0).map_err(e400)?;
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+
+    unsubscribe_subscriber(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(include_str!("unsubscribed.html")))
+}
+
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")?;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to unsubscribe subscriber")?;
+    unsubscribe_from_all_lists(&mut transaction, subscriber_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit unsubscription.")?;
+    Ok(())
+}
+
+/// Unsubscribing is not tied to a list: a subscriber who subscribes again later has to confirm
+/// each list anew.
+pub async fn unsubscribe_from_all_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE list_subscriptions SET status = 'unsubscribed' WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to unsubscribe subscriber from their lists")?;
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use std::fmt::{self, Debug};
+
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::{StatusCode, header},
+    web,
+};
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::configuration::WebhookSettings;
+use crate::routes::{error_chain_fmt, unsubscribe_from_all_lists};
+
+/// The events Postmark posts about the emails we sent, with only the fields we act on.
+///
+/// See <https://postmarkapp.com/developer/webhooks/webhooks-overview>.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType", rename_all_fields = "PascalCase")]
+enum PostmarkEvent {
+    Bounce {
+        email: String,
+        /// e.g. `HardBounce` or `SoftBounce`.
+        r#type: String,
+        /// Whether Postmark stopped sending to the address because of the bounce.
+        #[serde(default)]
+        inactive: bool,
+    },
+    SpamComplaint {
+        email: String,
+    },
+    /// An address was suppressed or reactivated, on Postmark's side or by the recipient.
+    SubscriptionChange {
+        recipient: String,
+        suppress_sending: bool,
+        suppression_reason: Option<String>,
+    },
+    /// Deliveries, opens and the like, which we have no use for.
+    #[serde(other)]
+    Other,
+}
+
+/// Receive delivery events from the email provider, so that addresses which bounce or complain
+/// are not sent to again.
+#[tracing::instrument(name = "Handle an email webhook", skip_all, fields(event=tracing::field::Empty))]
+pub async fn email_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate(&request, &settings)?;
+    let event: PostmarkEvent =
+        serde_json::from_slice(&body).map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+    tracing::Span::current().record("event", tracing::field::debug(&event));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get Postgres connection from Pool.")?;
+    match event {
+        PostmarkEvent::Bounce {
+            email,
+            r#type,
+            inactive: true,
+        } => {
+            suppress(&mut transaction, &email, &r#type).await?;
+            mark_as_bounced(&mut transaction, &email).await?;
+        }
+        // Soft bounces and the like may go away on their own, and are retried by Postmark.
+        PostmarkEvent::Bounce { .. } => {}
+        PostmarkEvent::SpamComplaint { email } => {
+            suppress(&mut transaction, &email, "SpamComplaint").await?;
+            unsubscribe(&mut transaction, &email).await?;
+        }
+        PostmarkEvent::SubscriptionChange {
+            recipient,
+            suppress_sending: true,
+            suppression_reason,
+        } => {
+            let reason = suppression_reason.unwrap_or_else(|| "ManualSuppression".into());
+            suppress(&mut transaction, &recipient, &reason).await?;
+            if reason == "HardBounce" {
+                mark_as_bounced(&mut transaction, &recipient).await?;
+            } else {
+                unsubscribe(&mut transaction, &recipient).await?;
+            }
+        }
+        PostmarkEvent::SubscriptionChange {
+            recipient,
+            suppress_sending: false,
+            ..
+        } => reactivate(&mut transaction, &recipient).await?,
+        PostmarkEvent::Other => {}
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit webhook event.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Accept either basic auth with the configured username and secret, or the secret alone in the
+/// `X-Webhook-Secret` header.
+fn authenticate(request: &HttpRequest, settings: &WebhookSettings) -> Result<(), WebhookError> {
+    let expected = settings.secret.expose_secret();
+    if let Some(secret) = request.headers().get("X-Webhook-Secret") {
+        return match secret.to_str() {
+            Ok(secret) if secrets_match(secret, expected) => Ok(()),
+            _ => Err(WebhookError::AuthError),
+        };
+    }
+
+    let credentials = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(WebhookError::AuthError)?;
+    match credentials.split_once(':') {
+        Some((username, secret))
+            if username == settings.username && secrets_match(secret, expected) =>
+        {
+            Ok(())
+        }
+        _ => Err(WebhookError::AuthError),
+    }
+}
+
+/// Compare in constant time, so that response times do not tell how much of a guess was right.
+fn secrets_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Stop sending anything to `email`, which the delivery workers check before every email.
+#[tracing::instrument(name = "Suppress an address", skip(transaction))]
+async fn suppress(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email, reason, suppressed_at)
+        VALUES (lower($1), $2, now())
+        ON CONFLICT (email) DO UPDATE SET reason = $2, suppressed_at = now()
+        "#,
+        email,
+        reason
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store suppression")?;
+    Ok(())
+}
+
+/// Someone who unsubscribed stays unsubscribed even if their address bounces later.
+#[tracing::instrument(name = "Mark a subscriber as bounced", skip(transaction))]
+async fn mark_as_bounced(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'bounced'
+        WHERE lower(email) = lower($1) AND status <> 'unsubscribed'
+        "#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark subscriber as bounced")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Unsubscribe an address", skip(transaction))]
+async fn unsubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE lower(email) = lower($1)
+        RETURNING id
+        "#,
+        email
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to unsubscribe subscriber")?;
+    if let Some(subscriber) = subscriber {
+        unsubscribe_from_all_lists(transaction, subscriber.id).await?;
+    }
+    Ok(())
+}
+
+/// Lift the suppression of an address. A subscriber it bounced for is confirmed again, while
+/// one who unsubscribed has to subscribe again.
+#[tracing::instrument(name = "Reactivate an address", skip(transaction))]
+async fn reactivate(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!("DELETE FROM suppressions WHERE email = lower($1)", email)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to delete suppression")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE lower(email) = lower($1) AND status = 'bounced'
+        "#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to reactivate subscriber")?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid webhook credentials")]
+    AuthError,
+    #[error("Invalid webhook payload: {0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::AuthError => StatusCode::UNAUTHORIZED,
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::AuthError = self {
+            response.insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="webhooks""#));
+        }
+        response.body(self.to_string())
+    }
+}
@@ -2,12 +2,17 @@ use std::future::{Ready, ready};
 
 use actix_session::{Session, SessionExt};
 use actix_web::FromRequest;
+use secrecy::{ExposeSecret, Secret};
 use uuid::Uuid;
 
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &str = "user_id";
+    const SESSION_VERSION_KEY: &str = "session_version";
+    const HALF_AUTHENTICATED_USER_ID_KEY: &str = "half_authenticated_user_id";
+    const FAILED_SECOND_FACTORS_KEY: &str = "failed_second_factors";
+    const TOTP_SECRET_TO_CONFIRM_KEY: &str = "totp_secret_to_confirm";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -21,6 +26,80 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    fn insert_session_version(
+        &self,
+        version: i32,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::SESSION_VERSION_KEY, version)
+    }
+
+    /// Sessions opened before versions were stored have none, and count as the first version.
+    pub fn get_session_version(&self) -> Result<i32, actix_session::SessionGetError> {
+        Ok(self.0.get(Self::SESSION_VERSION_KEY)?.unwrap_or(0))
+    }
+
+    /// Log a user in once they have entered every factor they need to.
+    pub fn log_in(
+        &self,
+        user_id: Uuid,
+        session_version: i32,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.renew();
+        self.0.remove(Self::HALF_AUTHENTICATED_USER_ID_KEY);
+        self.0.remove(Self::FAILED_SECOND_FACTORS_KEY);
+        self.insert_user_id(user_id)?;
+        self.insert_session_version(session_version)
+    }
+
+    /// The user has entered their password but not their second factor yet, which does not count
+    /// as being logged in.
+    pub fn insert_half_authenticated_user_id(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.renew();
+        self.0.insert(Self::HALF_AUTHENTICATED_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_half_authenticated_user_id(
+        &self,
+    ) -> Result<Option<Uuid>, actix_session::SessionGetError> {
+        self.0.get(Self::HALF_AUTHENTICATED_USER_ID_KEY)
+    }
+
+    /// Returns how many invalid second factors have been entered since the password.
+    pub fn record_failed_second_factor(&self) -> Result<u32, anyhow::Error> {
+        let failures = self
+            .0
+            .get::<u32>(Self::FAILED_SECOND_FACTORS_KEY)?
+            .unwrap_or(0)
+            + 1;
+        self.0.insert(Self::FAILED_SECOND_FACTORS_KEY, failures)?;
+        Ok(failures)
+    }
+
+    /// The secret of an authenticator being set up, until the user confirms it with a code.
+    pub fn insert_totp_secret_to_confirm(
+        &self,
+        secret: &Secret<String>,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0
+            .insert(Self::TOTP_SECRET_TO_CONFIRM_KEY, secret.expose_secret())
+    }
+
+    pub fn get_totp_secret_to_confirm(
+        &self,
+    ) -> Result<Option<Secret<String>>, actix_session::SessionGetError> {
+        Ok(self
+            .0
+            .get::<String>(Self::TOTP_SECRET_TO_CONFIRM_KEY)?
+            .map(Secret::new))
+    }
+
+    pub fn remove_totp_secret_to_confirm(&self) {
+        self.0.remove(Self::TOTP_SECRET_TO_CONFIRM_KEY);
+    }
+
     pub fn log_out(&self) {
         self.0.purge();
     }
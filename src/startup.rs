@@ -1,14 +1,29 @@
-use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
-use crate::routes::admin::{get_newsletters, post_newsletters};
+use crate::authentication::{reject_anonymous_users, require_admin, require_editor};
+use crate::clock::Clock;
+use crate::configuration::{DatabaseSettings, Settings, SubscriptionSettings, WebhookSettings};
+use crate::email_client::EmailTransport;
+use crate::routes::admin::{
+    cancel_newsletter, change_email, change_email_form, confirm_two_factor, count_issue_recipients,
+    create_draft, create_list, delete_subscriber, delete_user, disable_user, enable_user,
+    export_subscribers, get_draft, get_drafts, get_lists, get_newsletter_history,
+    get_newsletter_report, get_newsletters, get_scheduled_newsletters, get_subscriber,
+    get_subscribers, get_users, import_subscribers, import_subscribers_form, invite_user,
+    mark_subscriber_confirmed, mark_subscriber_unsubscribed, post_newsletters, preview_draft,
+    preview_newsletter, publish_draft, regenerate_recovery_codes, remove_subscriber_tag,
+    reschedule_newsletter, send_test_draft, set_subscriber_tag, turn_off_two_factor,
+    two_factor_form, update_draft,
+};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, subscription,
+    accept_invitation, accept_invitation_form, admin_dashboard, change_password,
+    change_password_form, confirm, email_webhook, forgot_password, forgot_password_form,
+    health_check, home, issue_archive, log_out, login, login_form, reset_password,
+    reset_password_form, second_factor, second_factor_form, subscription, track_click, track_open,
+    unsubscribe, unsubscribe_form, view_issue,
 };
 use actix_session::SessionMiddleware;
 use actix_session::storage::RedisSessionStore;
 use actix_web::cookie::Key;
+use actix_web::middleware::from_fn;
 use actix_web::{
     App, HttpServer,
     dev::Server,
@@ -17,6 +32,8 @@ use actix_web::{
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
@@ -26,6 +43,15 @@ pub struct Application {
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        Self::build_with_clock(configuration, Clock::System).await
+    }
+
+    /// Build the application with handlers reading the time from `clock`, e.g. a manual one in
+    /// tests.
+    pub async fn build_with_clock(
+        configuration: Settings,
+        clock: Clock,
+    ) -> Result<Self, anyhow::Error> {
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
@@ -49,6 +75,10 @@ impl Application {
                 configuration.application.base_url,
                 configuration.application.hmac_secret,
                 configuration.redis_uri,
+                configuration.application.shutdown_deadline,
+                configuration.subscriptions,
+                configuration.webhooks,
+                clock,
             )
             .await?,
         })
@@ -58,7 +88,16 @@ impl Application {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+    /// Serve requests until `shutdown` is cancelled, then let in-flight requests finish.
+    pub async fn run_until_stopped(
+        self,
+        shutdown: CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        let handle = self.server.handle();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            handle.stop(true).await;
+        });
         self.server.await
     }
 }
@@ -69,17 +108,26 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.with_db())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     address: std::net::TcpListener,
     db_pool: sqlx::PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailTransport>,
     base_url: String,
     hmac_secret: Secret<String>,
     redis_uri: Secret<String>,
+    shutdown_deadline: std::time::Duration,
+    subscription_settings: SubscriptionSettings,
+    webhook_settings: WebhookSettings,
+    clock: Clock,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = Data::new(db_pool);
-    let email_client = Data::new(email_client);
+    let email_client: Data<dyn EmailTransport> = Data::from(email_client);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let hmac = Data::new(HmacSecret(hmac_secret.clone()));
+    let subscription_settings = Data::new(subscription_settings);
+    let webhook_settings = Data::new(webhook_settings);
+    let clock = Data::new(clock);
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
     let message_store = actix_web_flash_messages::storage::CookieMessageStore::builder(
@@ -101,23 +149,177 @@ pub async fn run(
             .route("/health_check", web::get().to(health_check))
             .route("/subscription", web::post().to(subscription))
             .route("/subscription/confirm", web::get().to(confirm))
+            .route("/subscription/unsubscribe", web::get().to(unsubscribe_form))
+            .route("/subscription/unsubscribe", web::post().to(unsubscribe))
+            .route("/issues", web::get().to(issue_archive))
+            .route("/issues/{id}", web::get().to(view_issue))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/webhooks/email", web::post().to(email_webhook))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            .route("/login/forgot", web::get().to(forgot_password_form))
+            .route("/login/forgot", web::post().to(forgot_password))
+            .route("/login/reset", web::get().to(reset_password_form))
+            .route("/login/reset", web::post().to(reset_password))
+            .route("/login/two_factor", web::get().to(second_factor_form))
+            .route("/login/two_factor", web::post().to(second_factor))
+            .route("/invitation", web::get().to(accept_invitation_form))
+            .route("/invitation", web::post().to(accept_invitation))
             .service(
                 actix_web::web::scope("/admin")
-                    .wrap(actix_web::middleware::from_fn(reject_anonymous_users))
+                    .wrap(from_fn(reject_anonymous_users))
                     .route("/newsletters", web::get().to(get_newsletters))
-                    .route("/newsletters", web::post().to(post_newsletters))
+                    .route(
+                        "/newsletters",
+                        web::post()
+                            .to(post_newsletters)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route("/newsletters/preview", web::post().to(preview_newsletter))
+                    .route(
+                        "/newsletters/recipients",
+                        web::post().to(count_issue_recipients),
+                    )
+                    .route(
+                        "/newsletters/scheduled",
+                        web::get().to(get_scheduled_newsletters),
+                    )
+                    .route(
+                        "/newsletters/scheduled/{id}/reschedule",
+                        web::post()
+                            .to(reschedule_newsletter)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/newsletters/scheduled/{id}/cancel",
+                        web::post()
+                            .to(cancel_newsletter)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route("/newsletters/drafts", web::get().to(get_drafts))
+                    .route(
+                        "/newsletters/drafts",
+                        web::post().to(create_draft).wrap(from_fn(require_editor)),
+                    )
+                    .route("/newsletters/drafts/{id}", web::get().to(get_draft))
+                    .route(
+                        "/newsletters/drafts/{id}",
+                        web::post().to(update_draft).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{id}/preview",
+                        web::get().to(preview_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{id}/test",
+                        web::post()
+                            .to(send_test_draft)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{id}/publish",
+                        web::post().to(publish_draft).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/newsletters/history",
+                        web::get().to(get_newsletter_history),
+                    )
+                    .route("/newsletters/{id}", web::get().to(get_newsletter_report))
+                    .route("/lists", web::get().to(get_lists))
+                    .route(
+                        "/lists",
+                        web::post().to(create_list).wrap(from_fn(require_editor)),
+                    )
+                    .route("/subscribers", web::get().to(get_subscribers))
+                    .route(
+                        "/subscribers/import",
+                        web::get().to(import_subscribers_form),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::post()
+                            .to(import_subscribers)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route("/subscribers/export.csv", web::get().to(export_subscribers))
+                    .route("/subscribers/{id}", web::get().to(get_subscriber))
+                    .route(
+                        "/subscribers/{id}/confirm",
+                        web::post()
+                            .to(mark_subscriber_confirmed)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/subscribers/{id}/unsubscribe",
+                        web::post()
+                            .to(mark_subscriber_unsubscribed)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/subscribers/{id}/tags",
+                        web::post()
+                            .to(set_subscriber_tag)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/subscribers/{id}/tags/delete",
+                        web::post()
+                            .to(remove_subscriber_tag)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/subscribers/{id}/delete",
+                        web::post()
+                            .to(delete_subscriber)
+                            .wrap(from_fn(require_admin)),
+                    )
+                    .route(
+                        "/users",
+                        web::get().to(get_users).wrap(from_fn(require_admin)),
+                    )
+                    .route(
+                        "/users",
+                        web::post().to(invite_user).wrap(from_fn(require_admin)),
+                    )
+                    .route(
+                        "/users/{id}/disable",
+                        web::post().to(disable_user).wrap(from_fn(require_admin)),
+                    )
+                    .route(
+                        "/users/{id}/enable",
+                        web::post().to(enable_user).wrap(from_fn(require_admin)),
+                    )
+                    .route(
+                        "/users/{id}/delete",
+                        web::post().to(delete_user).wrap(from_fn(require_admin)),
+                    )
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/change_password", web::get().to(change_password_form))
                     .route("/change_password", web::post().to(change_password))
+                    .route("/email", web::get().to(change_email_form))
+                    .route("/email", web::post().to(change_email))
+                    .route("/two_factor", web::get().to(two_factor_form))
+                    .route("/two_factor", web::post().to(confirm_two_factor))
+                    .route(
+                        "/two_factor/recovery_codes",
+                        web::post().to(regenerate_recovery_codes),
+                    )
+                    .route("/two_factor/disable", web::post().to(turn_off_two_factor))
                     .route("/logout", web::post().to(log_out)),
             )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
             .app_data(hmac_secret.clone())
+            .app_data(hmac.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(clock.clone())
     })
+    // Shutdown is coordinated with the background workers in `run_until_stopped`.
+    .disable_signals()
+    .shutdown_timeout(shutdown_deadline.as_secs())
     .listen(address)?
     .run();
     Ok(server)
@@ -0,0 +1,81 @@
+use sqlx::PgPool;
+use sqlx::postgres::types::PgInterval;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    configuration::{Settings, SubscriptionSettings},
+    startup::get_connection_pool,
+};
+
+pub async fn run_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    while !shutdown.is_cancelled() {
+        if let Err(e) = delete_expired_subscriptions(&pool, &configuration.subscriptions).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired subscriptions"
+            );
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(Duration::from_secs(60 * 60)) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Delete the subscribers who never confirmed in time, along with every confirmation token
+/// that can no longer be used.
+#[tracing::instrument(
+    name = "Delete expired subscriptions",
+    skip_all,
+    fields(n_subscribers = tracing::field::Empty, n_tokens = tracing::field::Empty)
+)]
+pub async fn delete_expired_subscriptions(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<(), anyhow::Error> {
+    let pending_expiry: PgInterval = settings
+        .pending_expiry()
+        .try_into()
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    let confirmation_expiry: PgInterval = settings
+        .confirmation_expiry()
+        .try_into()
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    // A subscriber who asked for a new link recently gets to use it.
+    let n_subscribers = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions s
+        WHERE status = 'pending_confirmation'
+            AND subscribed_at + $1 < now()
+            AND NOT EXISTS (
+                SELECT 1 FROM subscription_tokens t
+                WHERE t.subscriber_id = s.id AND t.created_at + $1 >= now()
+            )
+        "#,
+        pending_expiry
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    let n_tokens = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE created_at + $1 < now()",
+        confirmation_expiry
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    tracing::Span::current()
+        .record("n_subscribers", n_subscribers)
+        .record("n_tokens", n_tokens);
+    Ok(())
+}
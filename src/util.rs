@@ -13,6 +13,66 @@ pub fn e400<T: std::fmt::Display + std::fmt::Debug + 'static>(e: T) -> actix_web
     actix_web::error::ErrorBadRequest(e)
 }
 
+/// Escape text so it can be safely embedded in html pages.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo [`escape_html`], e.g. to read a url out of an attribute. Other entities are left as is.
+pub fn unescape_html(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let entity = [
+            ("&amp;", '&'),
+            ("&lt;", '<'),
+            ("&gt;", '>'),
+            ("&quot;", '"'),
+            ("&#x27;", '\''),
+            ("&#39;", '\''),
+        ]
+        .into_iter()
+        .find(|(entity, _)| rest.starts_with(entity));
+        match entity {
+            Some((entity, c)) => {
+                unescaped.push(c);
+                rest = &rest[entity.len()..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// How many rows to skip to show `page` of a paginated view, counting pages from 1.
+///
+/// The page comes from the query string, so it is checked rather than trusted not to overflow.
+pub fn page_offset(page: i64, page_size: i64) -> Result<i64, actix_web::Error> {
+    if page < 1 {
+        return Err(e400("The page number must be at least 1"));
+    }
+    (page - 1)
+        .checked_mul(page_size)
+        .ok_or_else(|| e400("The page number is too large"))
+}
+
 pub fn see_other(path: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((actix_web::http::header::LOCATION, path))
@@ -28,3 +88,74 @@ pub async fn get_username(db_pool: &PgPool, uuid: Uuid) -> Result<String, anyhow
 
     Ok(name.username)
 }
+
+#[tracing::instrument(name = "Get user email", skip(db_pool))]
+pub async fn get_user_email(db_pool: &PgPool, uuid: Uuid) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!("SELECT email FROM users WHERE user_id = $1", uuid)
+        .fetch_one(db_pool)
+        .await
+        .context("Failed to perform a query to fetch a user's email from the database")?;
+
+    Ok(row.email)
+}
+
+/// A plain text version of an html email, for the text part of issues which were only written
+/// in html.
+///
+/// Links are listed as numbered footnotes after the text, headings are prefixed with `#` and list
+/// items with `*`.
+pub fn html_to_text(html: &str) -> Result<String, anyhow::Error> {
+    let text = html2text::config::plain()
+        .no_table_borders()
+        .no_link_wrapping()
+        .string_from_read(html.as_bytes(), 80)
+        .context("Failed to convert html to plain text")?;
+    Ok(text.trim_end().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_html, html_to_text, page_offset, unescape_html};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn unescaping_reverses_escaping() {
+        let s = r#"https://example.com/?a=1&b="2"&c='<3>'&d=&copy;"#;
+        assert_eq!(unescape_html(&escape_html(s)), s);
+        assert_eq!(unescape_html("a &copy; b &amp;amp;"), "a &copy; b &amp;");
+    }
+
+    #[test]
+    fn links_become_footnotes() {
+        let text = html_to_text(
+            r#"<p>Read <a href="https://example.com/?a=1&amp;b=2">the post</a> or
+            <a href="{{ unsubscribe_url }}">leave</a>.</p>"#,
+        )
+        .unwrap();
+
+        assert!(text.starts_with("Read [the post][1] or [leave][2]."));
+        assert!(text.contains("[1]: https://example.com/?a=1&b=2"));
+        assert!(text.ends_with("[2]: {{ unsubscribe_url }}"));
+    }
+
+    #[test]
+    fn headings_and_lists_are_kept() {
+        let text = html_to_text(
+            "<html><head><style>p { color: red }</style></head><body>\
+            <h1>Title</h1><h2>Section</h2><ul><li>One</li><li>Two</li></ul>\
+            <ol><li>First</li></ol></body></html>",
+        )
+        .unwrap();
+
+        assert_eq!(text, "# Title\n\n## Section\n* One\n* Two\n1. First");
+    }
+
+    #[test]
+    fn page_offsets_are_checked() {
+        assert_ok_eq!(page_offset(1, 50), 0);
+        assert_ok_eq!(page_offset(3, 50), 100);
+        assert_err!(page_offset(0, 50));
+        assert_err!(page_offset(-1, 50));
+        assert_err!(page_offset(i64::MAX, 50));
+    }
+}
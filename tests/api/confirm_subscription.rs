@@ -3,7 +3,7 @@ use wiremock::{
     matchers::{method, path},
 };
 
-use crate::helpers::{ConfirmationLinks, spawn_app};
+use crate::helpers::{ConfirmationLinks, create_unconfirmed_subscriber, spawn_app};
 
 #[actix_web::test]
 async fn confirmation_without_token_are_rejected_with_a_400() {
@@ -74,3 +74,113 @@ async fn clicking_confirmation_link_confirms_subscriber() {
     assert_eq!(status.email, "ursula_le_guin@gmail.com");
     assert_eq!(status.status, "confirmed");
 }
+
+#[actix_web::test]
+async fn expired_confirmation_links_are_rejected() {
+    let app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&app).await;
+
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '49 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(links.plain_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This confirmation link has expired"));
+    assert!(html.contains(r#"action="/subscription""#));
+
+    let subscription = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscription.status, "pending_confirmation");
+}
+
+#[actix_web::test]
+async fn confirmation_links_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&app).await;
+
+    let response = reqwest::get(links.plain_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Your subscription is confirmed"));
+
+    let tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+
+    let response = reqwest::get(links.plain_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This confirmation link is not valid"));
+}
+
+#[actix_web::test]
+async fn unknown_confirmation_tokens_show_the_invalid_link_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscription/confirm?subscription_token=not-a-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This confirmation link is not valid"));
+}
+
+#[actix_web::test]
+async fn using_a_second_link_after_confirming_shows_already_confirmed() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.to_string()).await;
+    app.post_subscriptions(body.to_string()).await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = ConfirmationLinks::get_confirmation_link(&requests[0], app.port);
+    let second_link = ConfirmationLinks::get_confirmation_link(&requests[1], app.port);
+
+    reqwest::get(second_link.plain_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = reqwest::get(first_link.plain_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Your subscription is already confirmed"));
+}
+
+#[actix_web::test]
+async fn confirmation_links_do_not_resubscribe_someone_who_unsubscribed() {
+    let app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(links.plain_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let subscription = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscription.status, "unsubscribed");
+}
@@ -0,0 +1,275 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+fn draft_body() -> serde_json::Value {
+    serde_json::json!({
+    "title": "Draft title",
+    "text": "Draft body as plain text",
+    "html": "<p>Draft body as HTML</p>",
+    })
+}
+
+async fn save_draft(app: &TestApp) -> Uuid {
+    let response = app.post_drafts(draft_body()).await;
+    let newsletter_issue_id = get_draft_id(app).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{newsletter_issue_id}"),
+    );
+    newsletter_issue_id
+}
+
+async fn get_draft_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'draft'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn set_test_user_email(app: &TestApp, email: &str) {
+    let response = app
+        .post_change_email(serde_json::json!({ "email": email }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/email");
+}
+
+async fn count_queued_tasks(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[actix_web::test]
+async fn drafts_are_not_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_issue_id = save_draft(&app).await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(count_queued_tasks(&app).await, 0);
+
+    let html = app.get_draft_html(newsletter_issue_id).await;
+    assert!(html.contains("The draft has been saved."));
+
+    let html = app.get_drafts_html().await;
+    assert!(html.contains("Draft title"));
+}
+
+#[actix_web::test]
+async fn drafts_can_be_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    let response = app
+        .post_draft(
+            newsletter_issue_id,
+            serde_json::json!({
+            "title": "Edited <title>",
+            "text": "Edited plain text",
+            "html": "<p>Edited HTML</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{newsletter_issue_id}"),
+    );
+
+    let html = app.get_draft_html(newsletter_issue_id).await;
+    assert!(html.contains("Edited &lt;title&gt;"));
+    assert!(html.contains("&lt;p&gt;Edited HTML&lt;/p&gt;"));
+    assert!(html.contains("Edited plain text"));
+}
+
+#[actix_web::test]
+async fn unknown_drafts_return_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_draft(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = app.post_draft(Uuid::new_v4(), draft_body()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn preview_shows_both_parts_of_the_draft() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    let response = app.get_draft_preview(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"sandbox="""#));
+    assert!(html.contains("&lt;p&gt;Draft body as HTML&lt;/p&gt;"));
+    assert!(html.contains("Draft body as plain text"));
+}
+
+#[actix_web::test]
+async fn test_sends_only_go_to_the_logged_in_user() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    set_test_user_email(&app, "admin@example.com").await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_draft_test(newsletter_issue_id).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{newsletter_issue_id}"),
+    );
+    let html = app.get_draft_html(newsletter_issue_id).await;
+    assert!(html.contains("A test email has been sent to admin@example.com."));
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["To"], "admin@example.com");
+    assert_eq!(body["Subject"], "[TEST] Draft title");
+
+    // The draft is still a draft and nothing was queued for the subscribers
+    assert_eq!(count_queued_tasks(&app).await, 0);
+    assert_eq!(get_draft_id(&app).await, newsletter_issue_id);
+}
+
+#[actix_web::test]
+async fn test_sends_require_an_email_address() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_draft_test(newsletter_issue_id).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{newsletter_issue_id}"),
+    );
+    let html = app.get_draft_html(newsletter_issue_id).await;
+    assert!(html.contains("set your email address"));
+}
+
+#[actix_web::test]
+async fn publishing_a_draft_delivers_it_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() });
+    let response = app.post_publish_draft(newsletter_issue_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/drafts");
+    let response = app.post_publish_draft(newsletter_issue_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/drafts");
+
+    let html = app.get_drafts_html().await;
+    assert!(html.contains("The newsletter has been published!"));
+    assert!(!html.contains("Draft title"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.get_draft(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_drafts() {
+    let app = spawn_app().await;
+
+    let response = app.post_drafts(draft_body()).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_draft(Uuid::new_v4()).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn users_can_change_their_email_address() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    set_test_user_email(&app, "admin@example.com").await;
+    let html = app.get_change_email_html().await;
+    assert!(html.contains("Your email address has been changed."));
+    assert!(html.contains(r#"value="admin@example.com""#));
+
+    set_test_user_email(&app, "not-an-email").await;
+    let html = app.get_change_email_html().await;
+    assert!(html.contains("The email address you entered is invalid."));
+    assert!(html.contains(r#"value="admin@example.com""#));
+}
+
+#[actix_web::test]
+async fn drafts_without_a_plain_text_part_get_one_generated_on_publish() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_drafts(serde_json::json!({
+            "title": "Draft title",
+            "html": "<ul><li>One</li><li>Two</li></ul>",
+        }))
+        .await;
+    let newsletter_issue_id = get_draft_id(&app).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{newsletter_issue_id}"),
+    );
+
+    let html = app
+        .get_draft_preview(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("Generated from the html content."));
+    assert!(html.contains("<pre>* One\n* Two</pre>"));
+
+    let body = serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() });
+    let response = app.post_publish_draft(newsletter_issue_id, &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/drafts");
+
+    let stored = sqlx::query!("SELECT text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.text_content, "* One\n* Two");
+}
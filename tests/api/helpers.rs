@@ -1,12 +1,25 @@
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 use argon2::PasswordHasher;
+use chrono::Utc;
+use fake::{
+    Fake,
+    faker::{internet::en::SafeEmail, name::en::Name},
+};
 use reqwest::Response;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgPool};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{DatabaseSettings, get_configuration};
-use zero2prod::email_client::EmailClient;
+use wiremock::{
+    Mock, MockBuilder, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+use zero2prod::clock::Clock;
+use zero2prod::configuration::{
+    DatabaseSettings, DeliverySettings, SubscriptionSettings, WebhookSettings, get_configuration,
+};
+use zero2prod::email_client::EmailTransport;
 use zero2prod::issue_delivery_workers::{TaskOutcome, try_execute_task};
 use zero2prod::startup::{Application, get_connection_pool};
 use zero2prod::telemetry;
@@ -16,10 +29,18 @@ pub struct TestApp {
     pub address: String,
     pub db_pool: sqlx::PgPool,
     pub email_server: MockServer,
-    pub email_client: EmailClient,
+    pub email_client: Arc<dyn EmailTransport>,
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub delivery_settings: DeliverySettings,
+    pub subscription_settings: SubscriptionSettings,
+    pub webhook_settings: WebhookSettings,
+    pub shutdown: CancellationToken,
+    /// Where handlers get the time from, e.g. to check one-time codes.
+    pub clock: Clock,
 }
 
 impl TestApp {
@@ -35,15 +56,29 @@ impl TestApp {
 
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
-            if let TaskOutcome::QueueEmpty = try_execute_task(&self.db_pool, &self.email_client)
-                .await
-                .unwrap()
+            if let TaskOutcome::QueueEmpty = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+                &self.delivery_settings,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
     }
 
+    /// Make tasks waiting to be retried eligible for delivery right away.
+    pub async fn make_pending_retries_due(&self) {
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&self.db_pool)
+            .await
+            .unwrap();
+    }
+
     pub async fn post_newsletters<T: serde::Serialize>(&self, body: T) -> Response {
         self.api_client
             .post(format!("{}/admin/newsletters", self.address))
@@ -73,6 +108,374 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn get_newsletter_report(&self, newsletter_issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{newsletter_issue_id}",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_newsletter_report_html(&self, newsletter_issue_id: Uuid) -> String {
+        self.get_newsletter_report(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_newsletter_history_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/history", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/issues", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_issue(&self, newsletter_issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!("{}/issues/{newsletter_issue_id}", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_scheduled_newsletters_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/scheduled", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_reschedule_newsletter<T: serde::Serialize>(
+        &self,
+        newsletter_issue_id: Uuid,
+        form: T,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/scheduled/{newsletter_issue_id}/reschedule",
+                self.address
+            ))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_cancel_newsletter(&self, newsletter_issue_id: Uuid) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/scheduled/{newsletter_issue_id}/cancel",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_drafts<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_drafts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/drafts", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_draft(&self, newsletter_issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{newsletter_issue_id}",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_draft_html(&self, newsletter_issue_id: Uuid) -> String {
+        self.get_draft(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_draft<T: serde::Serialize>(
+        &self,
+        newsletter_issue_id: Uuid,
+        form: T,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{newsletter_issue_id}",
+                self.address
+            ))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_draft_preview(&self, newsletter_issue_id: Uuid) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{newsletter_issue_id}/preview",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_draft_test(&self, newsletter_issue_id: Uuid) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{newsletter_issue_id}/test",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_publish_draft<T: serde::Serialize>(
+        &self,
+        newsletter_issue_id: Uuid,
+        form: T,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{newsletter_issue_id}/publish",
+                self.address
+            ))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_change_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/email", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_change_email<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/admin/email", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_lists<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/admin/lists", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_subscribers(&self, query: &str) -> Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{query}", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_subscribers_html(&self, query: &str) -> String {
+        self.get_subscribers(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_subscriber(&self, subscriber_id: Uuid) -> Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{subscriber_id}",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_subscriber_html(&self, subscriber_id: Uuid) -> String {
+        self.get_subscriber(subscriber_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_subscriber_action(&self, subscriber_id: Uuid, action: &str) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{subscriber_id}/{action}",
+                self.address
+            ))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_subscriber_tag<T: serde::Serialize>(
+        &self,
+        subscriber_id: Uuid,
+        form: T,
+    ) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{subscriber_id}/tags",
+                self.address
+            ))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_preview_newsletter<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/preview", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_count_recipients<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/recipients", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_import_subscribers(&self, csv: &str, confirmation: &str) -> Response {
+        let form = reqwest::multipart::Form::new()
+            .text("confirmation", confirmation.to_owned())
+            .part(
+                "file",
+                reqwest::multipart::Part::text(csv.to_owned())
+                    .file_name("subscribers.csv")
+                    .mime_str("text/csv")
+                    .unwrap(),
+            );
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", self.address))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_subscribers_export(&self) -> Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/export.csv", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    pub async fn get_users(&self) -> Response {
+        self.api_client
+            .get(format!("{}/admin/users", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_invite_user<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/admin/users", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_user_action(&self, user_id: Uuid, action: &str) -> Response {
+        self.api_client
+            .post(format!("{}/admin/users/{user_id}/{action}", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_invitation(&self, link: reqwest::Url) -> Response {
+        self.api_client
+            .get(link)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_invitation<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/invitation", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
             .get(format!("{}/login", self.address))
@@ -84,6 +487,70 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn post_forgot_password<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/login/forgot", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_password_reset(&self, link: reqwest::Url) -> Response {
+        self.api_client
+            .get(link)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_password_reset<T: serde::Serialize>(&self, form: T) -> Response {
+        self.api_client
+            .post(format!("{}/login/reset", self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_second_factor(&self) -> Response {
+        self.api_client
+            .get(format!("{}/login/two_factor", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_second_factor(&self, code: &str) -> Response {
+        self.api_client
+            .post(format!("{}/login/two_factor", self.address))
+            .form(&serde_json::json!({"code": code}))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two_factor", self.address))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is empty to confirm the authenticator being set up.
+    pub async fn post_two_factor(&self, action: &str, code: &str) -> Response {
+        self.api_client
+            .post(format!("{}/admin/two_factor{action}", self.address))
+            .form(&serde_json::json!({"code": code}))
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
     pub async fn get_admin_dashboard(&self) -> Response {
         self.api_client
             .get(format!("{}/admin/dashboard", self.address))
@@ -117,6 +584,45 @@ impl TestApp {
             .expect("Failed to execute Request")
     }
 
+    pub async fn get_unsubscribe(&self, link: reqwest::Url) -> Response {
+        self.api_client
+            .get(link)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn get_tracking_link(&self, link: reqwest::Url) -> Response {
+        self.api_client
+            .get(link)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    pub async fn post_unsubscribe(&self, link: reqwest::Url) -> Response {
+        self.api_client
+            .post(link)
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
+    /// Post an event the way the email provider does, with basic auth.
+    pub async fn post_email_webhook(&self, event: &serde_json::Value) -> Response {
+        self.api_client
+            .post(format!("{}/webhooks/email", self.address))
+            .basic_auth(
+                &self.webhook_settings.username,
+                Some(self.webhook_settings.secret.expose_secret()),
+            )
+            .json(event)
+            .send()
+            .await
+            .expect("Failed to execute Request")
+    }
+
     pub async fn post_logout(&self) -> Response {
         self.api_client
             .post(format!("{}/admin/logout", self.address))
@@ -158,13 +664,15 @@ pub async fn spawn_app() -> TestApp {
 
     configure_database(&configuration.database).await;
 
-    let application = Application::build(configuration.clone())
+    let clock = Clock::manual(Utc::now());
+    let application = Application::build_with_clock(configuration.clone(), clock.clone())
         .await
         .expect("Failed to build application");
     let application_port = application.port();
     let address = format!("http://127.0.0.1:{}", application.port());
 
-    _ = tokio::spawn(application.run_until_stopped());
+    let shutdown = CancellationToken::new();
+    _ = tokio::spawn(application.run_until_stopped(shutdown.clone()));
 
     let test_user = TestUser::generate();
 
@@ -182,6 +690,13 @@ pub async fn spawn_app() -> TestApp {
         email_client: configuration.email_client.client(),
         test_user,
         api_client,
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        delivery_settings: configuration.delivery,
+        subscription_settings: configuration.subscriptions,
+        webhook_settings: configuration.webhooks,
+        shutdown,
+        clock,
     };
 
     app.test_user.store(&app.db_pool).await;
@@ -215,18 +730,24 @@ pub struct TestUser {
     pub uuid: Uuid,
     pub username: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     fn generate() -> Self {
+        Self::generate_with_role("admin")
+    }
+
+    pub fn generate_with_role(role: &'static str) -> Self {
         Self {
             uuid: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, db_pool: &PgPool) {
+    pub async fn store(&self, db_pool: &PgPool) {
         let argon2 = argon2::Argon2::new(
             argon2::Algorithm::Argon2id,
             argon2::Version::V0x13,
@@ -240,10 +761,11 @@ impl TestUser {
             .unwrap();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash) VALUES ($1,$2,$3)",
+            "INSERT INTO users (user_id, username, password_hash, role) VALUES ($1,$2,$3,$4)",
             self.uuid,
             self.username,
-            hash.to_string()
+            hash.to_string(),
+            self.role
         )
         .execute(db_pool)
         .await
@@ -252,8 +774,8 @@ impl TestUser {
 
     pub async fn login(&self, app: &TestApp) {
         let login_body = serde_json::json!({
-            "username": app.test_user.username,
-            "password": app.test_user.password
+            "username": self.username,
+            "password": self.password
         });
 
         let response = app.post_login(login_body).await;
@@ -296,3 +818,81 @@ pub fn assert_is_redirect_to(response: &Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers()["Location"], location);
 }
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+
+    let _guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .named("Create unconfirmed subscriber")
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.to_string())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    ConfirmationLinks::get_confirmation_link(&response, app.port)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let links = create_unconfirmed_subscriber(app).await;
+
+    reqwest::get(links.plain_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Insert a subscriber confirmed on the default list.
+pub async fn insert_confirmed_subscriber(app: &TestApp, email: &str, name: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES($1, $2, $3, now(), 'confirmed')
+        "#,
+        id,
+        email,
+        name
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions(list_id, subscriber_id, status, subscribed_at)
+        SELECT list_id, $1, 'confirmed', now() FROM lists ORDER BY created_at LIMIT 1
+        "#,
+        id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+pub fn when_sending_a_batch() -> MockBuilder {
+    Mock::given(path("/email/batch")).and(method("POST"))
+}
+
+pub fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email")).and(method("POST"))
+}
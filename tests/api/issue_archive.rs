@@ -0,0 +1,123 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+async fn publish_newsletter(app: &TestApp, title: &str) -> Uuid {
+    let newsletter_request_body = serde_json::json!({
+    "title": title,
+    "text": "Newsletter body as plain text",
+    "html": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+#[actix_web::test]
+async fn published_issues_can_be_viewed_in_the_browser() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = publish_newsletter(&app, "Newsletter title").await;
+    app.post_logout().await;
+
+    let response = app.get_issue(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+
+    let html = app.get_issues_html().await;
+    assert!(html.contains(&format!(
+        r#"<a href="/issues/{newsletter_issue_id}">Newsletter title</a>"#
+    )));
+}
+
+#[actix_web::test]
+async fn unpublished_issues_are_not_public() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_drafts(serde_json::json!({
+    "title": "Draft title",
+    "text": "Draft body as plain text",
+    "html": "<p>Draft body as HTML</p>",
+    }))
+    .await;
+    let draft_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    let response = app.get_issue(draft_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(!app.get_issues_html().await.contains("Draft title"));
+
+    let response = app.get_issue(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn delivered_issues_link_to_their_web_view() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_issue_id = publish_newsletter(&app, "Newsletter title").await;
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let link = format!("{}/issues/{newsletter_issue_id}", app.base_url);
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&link));
+    assert!(body["TextBody"].as_str().unwrap().contains(&link));
+}
+
+#[actix_web::test]
+async fn history_lists_published_issues_with_their_recipient_count() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let newsletter_issue_id = publish_newsletter(&app, "Newsletter title").await;
+
+    let html = app.get_newsletter_history_html().await;
+    assert!(html.contains(&format!(
+        r#"<a href="/admin/newsletters/{newsletter_issue_id}">Newsletter title</a>"#
+    )));
+    assert!(html.contains("<td>1</td>"));
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_history() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/newsletters/history", app.address))
+        .send()
+        .await
+        .expect("Failed to execute Request");
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,231 @@
+use fake::Fake;
+use fake::faker::internet::en::SafeEmail;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    ConfirmationLinks, TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app,
+    when_sending_an_email,
+};
+
+/// Create a list through the admin area, the test user must be logged in.
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let response = app.post_lists(serde_json::json!({ "name": name })).await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    sqlx::query!("SELECT list_id FROM lists WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+async fn default_list(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT list_id FROM lists ORDER BY created_at LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+/// Subscribe to a list and return the confirmation link sent for it.
+async fn subscribe(app: &TestApp, email: &str, list_id: Uuid) -> ConfirmationLinks {
+    let _guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", email),
+        ("list_id", &list_id.to_string()),
+    ])
+    .unwrap();
+    let response = app.post_subscriptions(body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    ConfirmationLinks::get_confirmation_link(&request, app.port)
+}
+
+async fn subscribe_and_confirm(app: &TestApp, email: &str, list_id: Uuid) {
+    let links = subscribe(app, email, list_id).await;
+    reqwest::get(links.html_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+fn newsletter_body(list_ids: &[Uuid]) -> Vec<(&'static str, String)> {
+    let mut body = vec![
+        ("title", "Newsletter title".to_string()),
+        ("text", "Newsletter body as plain text".to_string()),
+        ("html", "<p>Newsletter body as HTML</p>".to_string()),
+        ("idempotency_key", Uuid::new_v4().to_string()),
+    ];
+    body.extend(list_ids.iter().map(|id| ("list_id", id.to_string())));
+    body
+}
+
+async fn list_status(app: &TestApp, email: &str, list_id: Uuid) -> String {
+    sqlx::query!(
+        r#"
+        SELECT l.status
+        FROM list_subscriptions l
+        JOIN subscriptions s ON s.id = l.subscriber_id
+        WHERE s.email = $1 AND l.list_id = $2
+        "#,
+        email,
+        list_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[actix_web::test]
+async fn lists_can_be_created() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    create_list(&app, "Release notes").await;
+    let html = app.get_lists_html().await;
+    assert!(html.contains("The list Release notes has been created."));
+    assert!(html.contains("<td>Release notes</td>"));
+
+    let response = app
+        .post_lists(serde_json::json!({ "name": "Release notes" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html = app.get_lists_html().await;
+    assert!(html.contains("There is already a list with this name."));
+}
+
+#[actix_web::test]
+async fn subscribing_to_an_unknown_list_returns_a_400() {
+    let app = spawn_app().await;
+
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("list_id", &Uuid::new_v4().to_string()),
+    ])
+    .unwrap();
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn each_list_subscription_is_confirmed_with_its_own_token() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let default_list = default_list(&app).await;
+    let other_list = create_list(&app, "Release notes").await;
+    let email: String = SafeEmail().fake();
+
+    subscribe_and_confirm(&app, &email, default_list).await;
+    let links = subscribe(&app, &email, other_list).await;
+    assert_eq!(list_status(&app, &email, default_list).await, "confirmed");
+    assert_eq!(
+        list_status(&app, &email, other_list).await,
+        "pending_confirmation"
+    );
+
+    let token = sqlx::query!("SELECT list_id FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(token.list_id, other_list);
+
+    reqwest::get(links.html_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(list_status(&app, &email, other_list).await, "confirmed");
+}
+
+#[actix_web::test]
+async fn issues_are_only_delivered_to_the_subscribers_of_their_lists() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let list_id = create_list(&app, "Release notes").await;
+    subscribe_and_confirm(&app, "release@example.com", list_id).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_body(&[list_id])).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["To"], "release@example.com");
+}
+
+#[actix_web::test]
+async fn subscribers_of_several_target_lists_get_the_issue_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let default_list = default_list(&app).await;
+    let other_list = create_list(&app, "Release notes").await;
+    subscribe_and_confirm(&app, "both@example.com", default_list).await;
+    subscribe_and_confirm(&app, "both@example.com", other_list).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter_body(&[default_list, other_list]))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_web::test]
+async fn publishing_to_an_unknown_list_returns_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter_body(&[Uuid::new_v4()]))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    app.dispatch_all_pending_emails().await;
+
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
@@ -2,7 +2,24 @@ mod change_password;
 mod check_health;
 mod confirm_subscription;
 mod dashboard;
+mod drafts;
 mod helpers;
+mod issue_archive;
+mod lists;
 mod login;
 mod newsletter;
+mod password_reset;
+mod roles;
+mod scheduled_newsletters;
+mod segments;
+mod shutdown;
+mod subscriber_import;
+mod subscribers;
 mod subscription;
+mod subscription_expiry;
+mod templates;
+mod tracking;
+mod two_factor;
+mod unsubscribe;
+mod users;
+mod webhooks;
@@ -1,15 +1,14 @@
 use std::time::Duration;
 
-use fake::{
-    Fake,
-    faker::{internet::en::SafeEmail, name::en::Name},
+use wiremock::{Mock, ResponseTemplate, matchers::any};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::{Email, EmailTransport, SendEmailResult};
+use zero2prod::issue_delivery_workers::try_execute_task;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    spawn_app, when_sending_a_batch, when_sending_an_email,
 };
-use wiremock::{
-    Mock, MockBuilder, ResponseTemplate,
-    matchers::{any, method, path},
-};
-
-use crate::helpers::{ConfirmationLinks, TestApp, assert_is_redirect_to, spawn_app};
 
 #[actix_web::test]
 async fn newsletters_are_not_delivered_for_unconfirmed_subscribers() {
@@ -170,48 +169,504 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     // Mock should verify that it has been sent more than once on drop.
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(serde_json::json!({
-        "name": name,
-        "email": email
-    }))
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+    "title": "Newsletter title",
+    "text": "Newsletter body as plain text",
+    "html": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[actix_web::test]
+async fn transient_delivery_failures_are_retried_later() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"delayed!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
     .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.delayed);
 
-    let _guard = when_sending_an_email()
+    let failed = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.count, 0);
+}
+
+#[actix_web::test]
+async fn a_retried_delivery_can_succeed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_an_email()
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
-        .named("Create unconfirmed subscriber")
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.to_string())
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    app.make_pending_retries_due().await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
         .await
-        .error_for_status()
         .unwrap();
+    assert_eq!(queued.count, 0);
+}
 
-    let response = app
+#[actix_web::test]
+async fn permanent_delivery_failures_are_not_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+
+    let failed = sqlx::query!("SELECT n_retries FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.n_retries, 0);
+}
+
+#[actix_web::test]
+async fn deliveries_are_dead_lettered_after_max_attempts() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let max_attempts = app.delivery_settings.max_attempts;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(503))
+        .expect(u64::from(max_attempts))
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    for _ in 0..max_attempts {
+        app.dispatch_all_pending_emails().await;
+        app.make_pending_retries_due().await;
+    }
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+
+    let failed = sqlx::query!("SELECT n_retries, error FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(i32::from(failed.n_retries), i32::from(max_attempts) - 1);
+    assert!(failed.error.contains("503"));
+}
+
+async fn get_newsletter_issue_id(app: &TestApp) -> uuid::Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[actix_web::test]
+async fn delivery_report_shows_the_status_of_each_delivery() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    let newsletter_issue_id = get_newsletter_issue_id(&app).await;
+
+    let html = app.get_newsletter_report_html(newsletter_issue_id).await;
+    assert!(html.contains("<tr><th>Queued</th><td>1</td></tr>"));
+    assert!(html.contains("<tr><th>Sent</th><td>0</td></tr>"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let html = app.get_newsletter_report_html(newsletter_issue_id).await;
+    assert!(html.contains("<tr><th>Queued</th><td>0</td></tr>"));
+    assert!(html.contains("<tr><th>Sent</th><td>1</td></tr>"));
+    assert!(html.contains("b7bc2f4a-e38e-4336-af7d-e6c392c2f817"));
+
+    let delivery =
+        sqlx::query!("SELECT status, n_attempts, provider_message_id FROM issue_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.n_attempts, 1);
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+}
+
+#[actix_web::test]
+async fn delivery_report_counts_failed_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let html = app
+        .get_newsletter_report_html(get_newsletter_issue_id(&app).await)
+        .await;
+    assert!(html.contains("<tr><th>Failed</th><td>1</td></tr>"));
+}
+
+#[actix_web::test]
+async fn delivery_report_of_unknown_issue_returns_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_newsletter_report(uuid::Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn delivery_report_rejects_out_of_range_pages() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    let newsletter_issue_id = get_newsletter_issue_id(&app).await;
+
+    for page in ["0", "-1", &i64::MAX.to_string()] {
+        let response = app
+            .api_client
+            .get(format!(
+                "{}/admin/newsletters/{newsletter_issue_id}?page={page}",
+                app.address
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 400, "page={page}");
+    }
+}
+
+#[actix_web::test]
+async fn user_must_be_authenticated_to_see_delivery_report() {
+    let app = spawn_app().await;
+
+    let response = app.get_newsletter_report(uuid::Uuid::new_v4()).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn issues_are_delivered_to_many_subscribers_in_one_batch() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "first-id"},
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "second-id"},
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "third-id"},
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
         .email_server
         .received_requests()
         .await
         .unwrap()
         .pop()
         .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 3);
+}
+
+#[actix_web::test]
+async fn only_failed_recipients_of_a_batch_are_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "first-id"},
+            {"ErrorCode": 405, "Message": "Not allowed to send"},
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+
+    let html = app
+        .get_newsletter_report_html(get_newsletter_issue_id(&app).await)
+        .await;
+    assert!(html.contains("<tr><th>Sent</th><td>1</td></tr>"));
+    assert!(html.contains("<tr><th>Queued</th><td>1</td></tr>"));
+}
+
+/// A transport which only reports on the first email of each batch.
+struct TruncatingTransport;
+
+#[async_trait::async_trait]
+impl EmailTransport for TruncatingTransport {
+    async fn send_email(
+        &self,
+        _recipient: &SubscriberEmail,
+        _subject: &str,
+        _html_content: &str,
+        _text_content: &str,
+        _headers: &[(&str, &str)],
+    ) -> SendEmailResult {
+        Ok(None)
+    }
+
+    async fn send_batch(&self, emails: &[Email]) -> Vec<SendEmailResult> {
+        emails.iter().take(1).map(|_| Ok(None)).collect()
+    }
+}
+
+#[actix_web::test]
+async fn recipients_without_a_result_are_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+
+    try_execute_task(
+        &app.db_pool,
+        &TruncatingTransport,
+        &app.base_url,
+        &app.hmac_secret,
+        &app.delivery_settings,
+    )
+    .await
+    .unwrap();
+
+    let task = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+    let html = app
+        .get_newsletter_report_html(get_newsletter_issue_id(&app).await)
+        .await;
+    assert!(html.contains("<tr><th>Sent</th><td>1</td></tr>"));
+    assert!(html.contains("<tr><th>Queued</th><td>1</td></tr>"));
+}
+
+#[actix_web::test]
+async fn rejected_recipients_of_a_batch_are_dead_lettered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 406, "Message": "Inactive recipient"},
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "second-id"},
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
 
-    ConfirmationLinks::get_confirmation_link(&response, app.port)
+    let failed = sqlx::query!("SELECT error FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(failed.error.contains("Inactive recipient"));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let links = create_unconfirmed_subscriber(app).await;
+#[actix_web::test]
+async fn concurrent_workers_do_not_deliver_the_same_email_twice() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
 
-    reqwest::get(links.plain_link)
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+
+    let mut delivery_settings = app.delivery_settings.clone();
+    delivery_settings.batch_size = 1;
+    let worker = || {
+        try_execute_task(
+            &app.db_pool,
+            app.email_client.as_ref(),
+            &app.base_url,
+            &app.hmac_secret,
+            &delivery_settings,
+        )
+    };
+    let (first, second) = tokio::join!(worker(), worker());
+    first.unwrap();
+    second.unwrap();
+
+    let recipients: Vec<_> = app
+        .email_server
+        .received_requests()
         .await
         .unwrap()
-        .error_for_status()
+        .iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .filter(|body| body["Subject"] == "Newsletter title")
+        .map(|body| body["To"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(recipients.len(), 2);
+    assert_ne!(recipients[0], recipients[1]);
+}
+
+#[actix_web::test]
+async fn the_plain_text_part_is_generated_when_left_empty() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html": r#"<h1>News</h1><p>Read <a href="https://example.com/post">the post</a>.</p>"#,
+            "text": "",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let expected = "# News\n\nRead [the post][1].\n\n[1]: https://example.com/post";
+    let stored = sqlx::query!("SELECT text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.text_content, expected);
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
         .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert!(body["TextBody"].as_str().unwrap().starts_with(expected));
 }
 
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
+#[actix_web::test]
+async fn the_publish_form_can_be_previewed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_preview_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html = response.text().await.unwrap();
+    assert!(html.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(html.contains("Generated from the html content."));
+    assert!(html.contains("<pre>Newsletter body as HTML</pre>"));
 }
@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use chrono::TimeDelta;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    ConfirmationLinks, TestApp, TestUser, assert_is_redirect_to, spawn_app, when_sending_an_email,
+};
+
+const EMAIL: &str = "ursula@example.com";
+const SENT: &str = "If there is an account with this username or email address, \
+                    a link to reset its password has been sent to its email address.";
+
+async fn set_email(app: &TestApp, user_id: Uuid, email: &str) {
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        email,
+        user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// The emails are sent in the background, after the response.
+async fn wait_for_emails(app: &TestApp, n: usize) -> Vec<wiremock::Request> {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.len() >= n {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{n} emails were not sent in time");
+}
+
+/// Ask for a link to reset the test user's password and return it.
+async fn request_reset_link(app: &TestApp) -> reqwest::Url {
+    let n_sent = app.email_server.received_requests().await.unwrap().len();
+    let response = app
+        .post_forgot_password(serde_json::json!({"username_or_email": app.test_user.username}))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let requests = wait_for_emails(app, n_sent + 1).await;
+    let links = ConfirmationLinks::get_confirmation_link(requests.last().unwrap(), app.port);
+    assert_eq!(links.html_link, links.plain_link);
+    links.html_link
+}
+
+fn reset_form(
+    link: &reqwest::Url,
+    new_password: &str,
+    confirm_password: &str,
+) -> serde_json::Value {
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    serde_json::json!({
+        "token": token,
+        "new_password": new_password,
+        "confirm_password": confirm_password
+    })
+}
+
+async fn spawn_app_with_email() -> TestApp {
+    let app = spawn_app().await;
+    set_email(&app, app.test_user.uuid, EMAIL).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+#[actix_web::test]
+async fn the_response_is_the_same_whether_or_not_there_is_an_account() {
+    let app = spawn_app_with_email().await;
+    let disabled = TestUser::generate_with_role("viewer");
+    disabled.store(&app.db_pool).await;
+    set_email(&app, disabled.uuid, "disabled@example.com").await;
+    sqlx::query!(
+        "UPDATE users SET disabled = true WHERE user_id = $1",
+        disabled.uuid
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let without_email = TestUser::generate_with_role("viewer");
+    without_email.store(&app.db_pool).await;
+
+    for username_or_email in [
+        "nobody",
+        "nobody@example.com",
+        &disabled.username,
+        "disabled@example.com",
+        &without_email.username,
+        // Addresses are matched whatever their case.
+        "Ursula@Example.com",
+    ] {
+        let response = app
+            .post_forgot_password(serde_json::json!({"username_or_email": username_or_email}))
+            .await;
+        assert_is_redirect_to(&response, "/login");
+        let html_page = app.get_login_html().await;
+        assert!(html_page.contains(SENT), "{username_or_email}");
+    }
+
+    // Only the user who can log in gets an email.
+    wait_for_emails(&app, 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["To"], EMAIL);
+}
+
+#[actix_web::test]
+async fn a_reset_sets_a_new_password_and_logs_out_every_session() {
+    let app = spawn_app_with_email().await;
+    let other_session = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let dashboard = format!("{}/admin/dashboard", app.address);
+    let response = other_session
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let link = request_reset_link(&app).await;
+    let response = app.get_password_reset(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_password_reset(reset_form(&link, &new_password, &new_password))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your password has been reset, you can now log in."));
+
+    let response = other_session.get(&dashboard).send().await.unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(serde_json::json!({
+            "username": app.test_user.username,
+            "password": new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Each link works once.
+    let response = app.get_password_reset(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_password_reset(reset_form(&link, &new_password, &new_password))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_web::test]
+async fn a_reset_invalidates_every_link_sent_to_the_user() {
+    let app = spawn_app_with_email().await;
+    let first_link = request_reset_link(&app).await;
+    let second_link = request_reset_link(&app).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_password_reset(reset_form(&second_link, &new_password, &new_password))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_password_reset(first_link).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_web::test]
+async fn invalid_new_passwords_are_rejected_and_the_link_keeps_working() {
+    let app = spawn_app_with_email().await;
+    let link = request_reset_link(&app).await;
+    let form_url = format!("/login/reset?{}", link.query().unwrap());
+
+    let cases = [
+        (
+            reset_form(&link, "a-long-enough-password", "another-long-password"),
+            "You entered two different new passwords - the field values must match.",
+        ),
+        (
+            reset_form(&link, "too-short", "too-short"),
+            "The new password you entered must be at least 12 characters long.",
+        ),
+    ];
+    for (form, message) in cases {
+        let response = app.post_password_reset(form).await;
+        assert_is_redirect_to(&response, &form_url);
+        let html_page = app
+            .get_password_reset(link.clone())
+            .await
+            .text()
+            .await
+            .unwrap();
+        assert!(html_page.contains(message), "{message}");
+    }
+    // The old password still works.
+    app.test_user.login(&app).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_password_reset(reset_form(&link, &new_password, &new_password))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn expired_reset_links_are_rejected() {
+    let app = spawn_app_with_email().await;
+    let link = request_reset_link(&app).await;
+
+    app.clock.advance(TimeDelta::hours(2));
+
+    let response = app.get_password_reset(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 410);
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_password_reset(reset_form(&link, &new_password, &new_password))
+        .await;
+    assert_eq!(response.status().as_u16(), 410);
+    app.test_user.login(&app).await;
+}
+
+#[actix_web::test]
+async fn expired_reset_tokens_are_deleted_when_a_new_link_is_sent() {
+    let app = spawn_app_with_email().await;
+    request_reset_link(&app).await;
+    app.clock.advance(TimeDelta::hours(2));
+
+    let link = request_reset_link(&app).await;
+
+    let n_tokens = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM password_reset_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tokens, 1);
+    let response = app.get_password_reset(link).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
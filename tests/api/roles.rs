@@ -0,0 +1,99 @@
+use uuid::Uuid;
+
+use crate::helpers::{
+    TestApp, TestUser, assert_is_redirect_to, insert_confirmed_subscriber, spawn_app,
+};
+
+async fn log_in_as(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::generate_with_role(role);
+    user.store(&app.db_pool).await;
+    user.login(app).await;
+    user
+}
+
+fn newsletter_form() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "html": "<p>Newsletter body as HTML</p>",
+        "text": "Newsletter body as plain text",
+        "idempotency_key": Uuid::new_v4().to_string()
+    })
+}
+
+async fn n_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[actix_web::test]
+async fn viewers_can_look_but_not_publish() {
+    let app = spawn_app().await;
+    log_in_as(&app, "viewer").await;
+
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("Newsletter History"));
+
+    let response = app.post_newsletters(newsletter_form()).await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(n_issues(&app).await, 0);
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("(viewer)"));
+    assert!(html_page.contains("<p><i>Only editors and admins can do this.</i></p>"));
+}
+
+#[actix_web::test]
+async fn editors_can_publish_but_not_delete_subscribers() {
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    log_in_as(&app, "editor").await;
+
+    let response = app.post_newsletters(newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(n_issues(&app).await, 1);
+
+    let response = app.post_subscriber_action(subscriber_id, "delete").await;
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<p><i>Only admins can do this.</i></p>"));
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[actix_web::test]
+async fn a_new_role_applies_without_logging_in_again() {
+    let app = spawn_app().await;
+    let user = log_in_as(&app, "editor").await;
+
+    sqlx::query!(
+        "UPDATE users SET role = 'viewer' WHERE user_id = $1",
+        user.uuid
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.post_newsletters(newsletter_form()).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[actix_web::test]
+async fn deleted_users_are_logged_out() {
+    let app = spawn_app().await;
+    let user = log_in_as(&app, "admin").await;
+
+    sqlx::query!("DELETE FROM users WHERE user_id = $1", user.uuid)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
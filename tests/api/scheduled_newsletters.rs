@@ -0,0 +1,220 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+use zero2prod::issue_scheduling_workers::publish_due_issues;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+async fn schedule_newsletter(app: &TestApp, scheduled_for: &str) -> reqwest::Response {
+    let newsletter_request_body = serde_json::json!({
+    "title": "Scheduled newsletter title",
+    "text": "Newsletter body as plain text",
+    "html": "<p>Newsletter body as HTML</p>",
+    "scheduled_for": scheduled_for,
+    "idempotency_key": Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(newsletter_request_body).await
+}
+
+async fn get_scheduled_issue_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'scheduled'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn make_scheduled_issues_due(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now() WHERE status = 'scheduled'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+fn tomorrow() -> String {
+    (Utc::now() + Duration::days(1))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+#[actix_web::test]
+async fn scheduled_newsletters_are_not_delivered_right_away() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = schedule_newsletter(&app, &tomorrow()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html = app.get_newsletters_html().await;
+    assert!(html.contains("The newsletter has been scheduled for"));
+
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    app.dispatch_all_pending_emails().await;
+
+    let html = app.get_scheduled_newsletters_html().await;
+    assert!(html.contains("Scheduled newsletter title"));
+}
+
+#[actix_web::test]
+async fn scheduled_newsletters_are_delivered_once_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    schedule_newsletter(&app, &tomorrow()).await;
+    make_scheduled_issues_due(&app).await;
+
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 1);
+    app.dispatch_all_pending_emails().await;
+
+    let issue = sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "published");
+    assert!(issue.published_at.is_some());
+
+    let html = app.get_scheduled_newsletters_html().await;
+    assert!(!html.contains("Scheduled newsletter title"));
+}
+
+#[actix_web::test]
+async fn cancelled_newsletters_are_never_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    schedule_newsletter(&app, &tomorrow()).await;
+    let newsletter_issue_id = get_scheduled_issue_id(&app).await;
+
+    let response = app.post_cancel_newsletter(newsletter_issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+    let html = app.get_scheduled_newsletters_html().await;
+    assert!(html.contains("<p><i>The newsletter has been cancelled.</i></p>"));
+
+    make_scheduled_issues_due(&app).await;
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_web::test]
+async fn scheduled_newsletters_can_be_rescheduled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    schedule_newsletter(&app, &tomorrow()).await;
+    let newsletter_issue_id = get_scheduled_issue_id(&app).await;
+
+    let next_week = Utc::now() + Duration::days(7);
+    let response = app
+        .post_reschedule_newsletter(
+            newsletter_issue_id,
+            serde_json::json!({"scheduled_for": next_week.format("%Y-%m-%dT%H:%M").to_string()}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    let html = app.get_scheduled_newsletters_html().await;
+    assert!(html.contains("The newsletter has been rescheduled"));
+    assert!(html.contains(&next_week.format("%Y-%m-%d %H:%M").to_string()));
+}
+
+#[actix_web::test]
+async fn invalid_reschedule_times_are_shown_escaped() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    schedule_newsletter(&app, &tomorrow()).await;
+    let newsletter_issue_id = get_scheduled_issue_id(&app).await;
+
+    let response = app
+        .post_reschedule_newsletter(
+            newsletter_issue_id,
+            serde_json::json!({"scheduled_for": "<script>alert(1)</script>"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    let html = app.get_scheduled_newsletters_html().await;
+    assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(!html.contains("<script>"));
+}
+
+#[actix_web::test]
+async fn published_newsletters_cannot_be_cancelled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    schedule_newsletter(&app, &tomorrow()).await;
+    let newsletter_issue_id = get_scheduled_issue_id(&app).await;
+    make_scheduled_issues_due(&app).await;
+    publish_due_issues(&app.db_pool).await.unwrap();
+
+    app.post_cancel_newsletter(newsletter_issue_id).await;
+    let html = app.get_scheduled_newsletters_html().await;
+    assert!(html.contains("<p><i>This newsletter is no longer scheduled.</i></p>"));
+
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "published");
+}
+
+#[actix_web::test]
+async fn newsletters_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let yesterday = (Utc::now() - Duration::days(1)).to_rfc3339();
+    let response = schedule_newsletter(&app, &yesterday).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn replaying_a_schedule_after_its_time_returns_the_saved_response() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Scheduled newsletter title",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "scheduled_for": (Utc::now() + Duration::seconds(2)).to_rfc3339(),
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+}
@@ -0,0 +1,128 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, insert_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+async fn tag(app: &TestApp, subscriber_id: Uuid, key: &str, value: &str) {
+    let response = app
+        .post_subscriber_tag(
+            subscriber_id,
+            serde_json::json!({ "key": key, "value": value }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+}
+
+fn newsletter_body(segment: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        "segment": segment,
+        "idempotency_key": Uuid::new_v4().to_string()
+    })
+}
+
+#[actix_web::test]
+async fn tags_are_shown_on_the_subscriber_page() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "pro@example.com", "le guin").await;
+
+    tag(&app, subscriber_id, "plan", "free").await;
+    tag(&app, subscriber_id, "plan", "pro").await;
+    let html = app.get_subscriber_html(subscriber_id).await;
+    assert!(html.contains("The tag plan has been set."));
+    assert!(html.contains("<td>pro</td>"));
+    assert!(!html.contains("<td>free</td>"));
+
+    tag(&app, subscriber_id, "not", "a key").await;
+    let html = app.get_subscriber_html(subscriber_id).await;
+    assert!(html.contains("Tag names can only contain"));
+}
+
+#[actix_web::test]
+async fn issues_are_only_delivered_to_subscribers_in_their_segment() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let pro = insert_confirmed_subscriber(&app, "pro@example.com", "le guin").await;
+    let free = insert_confirmed_subscriber(&app, "free@example.com", "le guin").await;
+    insert_confirmed_subscriber(&app, "untagged@example.com", "le guin").await;
+    tag(&app, pro, "plan", "pro").await;
+    tag(&app, pro, "locale", "de").await;
+    tag(&app, free, "plan", "free").await;
+    tag(&app, free, "locale", "de").await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter_body(
+            "locale = de and not (plan = free or plan = \"trial\")",
+        ))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["To"], "pro@example.com");
+}
+
+#[actix_web::test]
+async fn invalid_segments_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "pro@example.com", "le guin").await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter_body("plan = 'pro' OR 1=1"))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    app.dispatch_all_pending_emails().await;
+
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[actix_web::test]
+async fn the_recipient_count_matches_the_segment() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let pro = insert_confirmed_subscriber(&app, "pro@example.com", "le guin").await;
+    insert_confirmed_subscriber(&app, "free@example.com", "le guin").await;
+    tag(&app, pro, "plan", "pro").await;
+
+    let response = app.post_count_recipients(newsletter_body("plan")).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This issue would be sent to 1 subscribers."));
+
+    let response = app.post_count_recipients(newsletter_body("")).await;
+    let html = response.text().await.unwrap();
+    assert!(html.contains("This issue would be sent to 2 subscribers."));
+
+    let response = app.post_count_recipients(newsletter_body("plan =")).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use wiremock::ResponseTemplate;
+use zero2prod::issue_delivery_workers::worker_loop;
+
+use crate::helpers::{TestApp, create_confirmed_subscriber, spawn_app, when_sending_an_email};
+
+fn spawn_worker(
+    app: &TestApp,
+    shutdown: CancellationToken,
+) -> tokio::task::JoinHandle<Result<(), anyhow::Error>> {
+    tokio::spawn(worker_loop(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.base_url.clone(),
+        app.hmac_secret.clone(),
+        app.delivery_settings.clone(),
+        shutdown,
+    ))
+}
+
+#[actix_web::test]
+async fn the_api_stops_once_shutdown_is_requested() {
+    let app = spawn_app().await;
+    let health_check = format!("{}/health_check", app.address);
+    // Open connections are only closed once the shutdown deadline passes, so every check has to
+    // make a new one.
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+    client
+        .get(&health_check)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while client.get(&health_check).send().await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The API kept serving requests after shutdown");
+}
+
+#[actix_web::test]
+async fn an_idle_worker_stops_right_away() {
+    let app = spawn_app().await;
+    let shutdown = CancellationToken::new();
+    let worker = spawn_worker(&app, shutdown.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    shutdown.cancel();
+
+    let outcome = tokio::time::timeout(Duration::from_secs(1), worker)
+        .await
+        .expect("The worker did not stop");
+    outcome.unwrap().unwrap();
+}
+
+#[actix_web::test]
+async fn a_worker_finishes_its_in_flight_delivery_before_stopping() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+    "title": "Newsletter title",
+    "text": "Newsletter body as plain text",
+    "html": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(newsletter_request_body).await;
+
+    let shutdown = CancellationToken::new();
+    let worker = spawn_worker(&app, shutdown.clone());
+
+    // Wait for the delivery to be in flight
+    let n_requests_before = app.email_server.received_requests().await.unwrap().len();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while app.email_server.received_requests().await.unwrap().len() == n_requests_before {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The worker never sent the issue");
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("The worker did not stop")
+        .unwrap()
+        .unwrap();
+
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+}
@@ -0,0 +1,200 @@
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    ConfirmationLinks, TestApp, assert_is_redirect_to, spawn_app, when_sending_an_email,
+};
+
+async fn get_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.status))
+        .collect()
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_import_or_export() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_import_subscribers("email,name\na@example.com,A\n", "confirmed")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_subscribers_export().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn valid_rows_are_imported_and_invalid_ones_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "\
+email,name,status
+ursula@example.com,Ursula,
+octavia@example.com,Octavia,unsubscribed
+not-an-email,Nobody,
+nora@example.com,,
+ted@example.com,Ted,vip
+";
+
+    let response = app.post_import_subscribers(csv, "confirmed").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("2 subscribers imported, 0 already subscribed"));
+    assert!(html.contains("<td>4</td>"));
+    assert!(html.contains("<td>5</td>"));
+    assert!(html.contains("<td>6</td>"));
+    assert!(html.contains("is not a subscriber status"));
+
+    assert_eq!(
+        get_statuses(&app).await,
+        vec![
+            ("octavia@example.com".into(), "unsubscribed".into()),
+            ("ursula@example.com".into(), "confirmed".into()),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn existing_subscribers_are_not_imported_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_import_subscribers("email,name\nursula@example.com,Ursula\n", "confirmed")
+        .await;
+
+    let csv = "email,name,status\nursula@example.com,Someone else,unsubscribed\nursula@example.com,Ursula,\n";
+    let html = app
+        .post_import_subscribers(csv, "confirmed")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("0 subscribers imported, 2 already subscribed"));
+    assert_eq!(
+        get_statuses(&app).await,
+        vec![("ursula@example.com".into(), "confirmed".into())]
+    );
+}
+
+#[actix_web::test]
+async fn imported_subscribers_can_be_asked_to_confirm() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let html = app
+        .post_import_subscribers("email,name\nursula@example.com,Ursula\n", "send")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("1 confirmation emails sent"));
+    assert_eq!(
+        get_statuses(&app).await,
+        vec![("ursula@example.com".into(), "pending_confirmation".into())]
+    );
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = ConfirmationLinks::get_confirmation_link(request, app.port);
+    reqwest::get(link.plain_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        get_statuses(&app).await,
+        vec![("ursula@example.com".into(), "confirmed".into())]
+    );
+}
+
+#[actix_web::test]
+async fn files_without_the_required_columns_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_import_subscribers("address\nursula@example.com\n", "confirmed")
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn fields_after_the_file_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let form = reqwest::multipart::Form::new()
+        .text("file", "email,name\nursula@example.com,Ursula\n")
+        .text("confirmation", "confirmed");
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/subscribers/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute Request");
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(get_statuses(&app).await.is_empty());
+}
+
+#[actix_web::test]
+async fn exported_subscribers_can_be_imported_back() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "\
+email,name,status
+ursula@example.com,\"Le Guin, Ursula\",confirmed
+octavia@example.com,Octavia,unsubscribed
+";
+    app.post_import_subscribers(csv, "confirmed").await;
+
+    let response = app.get_subscribers_export().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let export = response.text().await.unwrap();
+    let mut lines = export.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with("octavia@example.com,Octavia,unsubscribed,")
+    );
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with("ursula@example.com,\"Le Guin, Ursula\",confirmed,")
+    );
+
+    sqlx::query!("DELETE FROM subscriptions")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let html = app
+        .post_import_subscribers(&export, "send")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("2 subscribers imported"));
+    assert_eq!(
+        get_statuses(&app).await,
+        vec![
+            ("octavia@example.com".into(), "unsubscribed".into()),
+            ("ursula@example.com".into(), "confirmed".into()),
+        ]
+    );
+}
@@ -0,0 +1,245 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    spawn_app, when_sending_an_email,
+};
+
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES($1, $2, $3, now(), $4)
+        "#,
+        id,
+        email,
+        name,
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+async fn get_subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "a@example.com", "A", "confirmed").await;
+
+    let response = app.get_subscriber(subscriber_id).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_subscriber_action(subscriber_id, "delete").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn subscribers_can_be_searched_by_email_name_and_status() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+    insert_subscriber(
+        &app,
+        "octavia@example.com",
+        "Octavia",
+        "pending_confirmation",
+    )
+    .await;
+    insert_subscriber(&app, "n.k.jemisin@example.com", "Nora_", "unsubscribed").await;
+
+    let html = app.get_subscribers_html("").await;
+    assert!(html.contains("3 subscribers found"));
+
+    let html = app.get_subscribers_html("search=URSULA").await;
+    assert!(html.contains("ursula@example.com"));
+    assert!(!html.contains("octavia@example.com"));
+
+    let html = app.get_subscribers_html("search=octavia").await;
+    assert!(html.contains("octavia@example.com"));
+    assert!(!html.contains("ursula@example.com"));
+
+    // Wildcards are matched literally
+    let html = app.get_subscribers_html("search=_").await;
+    assert!(html.contains("1 subscribers found"));
+
+    let html = app.get_subscribers_html("status=unsubscribed").await;
+    assert!(html.contains("n.k.jemisin@example.com"));
+    assert!(html.contains("1 subscribers found"));
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers?status=nonsense", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn subscribers_are_paginated() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for i in 0..51 {
+        insert_subscriber(&app, &format!("{i}@example.com"), "Name", "confirmed").await;
+    }
+
+    let html = app.get_subscribers_html("status=confirmed").await;
+    assert!(html.contains("51 subscribers found"));
+    assert!(html.contains("page=2"));
+    assert_eq!(html.matches("<tr>").count(), 51);
+
+    let html = app.get_subscribers_html("status=confirmed&page=2").await;
+    assert_eq!(html.matches("<tr>").count(), 2);
+    assert!(html.contains("page=1"));
+}
+
+#[actix_web::test]
+async fn out_of_range_pages_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for page in ["0", "-1", &i64::MAX.to_string()] {
+        let response = app.get_subscribers(&format!("page={page}")).await;
+        assert_eq!(response.status().as_u16(), 400, "page={page}");
+    }
+}
+
+#[actix_web::test]
+async fn the_subscriber_page_shows_their_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let html = app.get_subscriber_html(get_subscriber_id(&app).await).await;
+    assert!(html.contains("confirmed"));
+    assert!(html.contains("Newsletter title"));
+    assert!(html.contains("sent"));
+
+    let response = app.get_subscriber(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn pending_subscribers_can_be_confirmed_manually() {
+    let app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let subscriber_id = get_subscriber_id(&app).await;
+
+    let response = app.post_subscriber_action(subscriber_id, "confirm").await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let html = app.get_subscriber_html(subscriber_id).await;
+    assert!(html.contains("The subscriber has been confirmed."));
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "confirmed");
+    // The confirmation link is no longer needed
+    let response = reqwest::get(links.plain_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.post_subscriber_action(subscriber_id, "confirm").await;
+    let html = app.get_subscriber_html(subscriber_id).await;
+    assert!(html.contains("Only subscriptions pending confirmation can be confirmed."));
+}
+
+#[actix_web::test]
+async fn unsubscribing_cancels_queued_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let subscriber_id = get_subscriber_id(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+
+    let response = app
+        .post_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let html = app.get_subscriber_html(subscriber_id).await;
+    assert!(html.contains("The subscriber has been unsubscribed."));
+    assert!(html.contains("skipped_unsubscribed"));
+
+    app.dispatch_all_pending_emails().await;
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[actix_web::test]
+async fn deleting_a_subscriber_removes_everything_pending_for_them() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    for subscriber in subscribers {
+        let response = app.post_subscriber_action(subscriber.id, "delete").await;
+        assert_is_redirect_to(&response, "/admin/subscribers");
+        let html = app.get_subscribers_html("").await;
+        assert!(html.contains("has been deleted."));
+        let response = app.get_subscriber(subscriber.id).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+
+    let html = app.get_subscribers_html("").await;
+    assert!(html.contains("0 subscribers found"));
+
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT COUNT(*) FROM issue_delivery_queue) AS "queued!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.queued, 0);
+}
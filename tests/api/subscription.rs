@@ -110,3 +110,182 @@ async fn subscribe_fails_for_fatal_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[actix_web::test]
+async fn subscribing_again_while_pending_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = ConfirmationLinks::get_confirmation_link(&requests[0], app.port);
+    let second_link = ConfirmationLinks::get_confirmation_link(&requests[1], app.port);
+    assert_ne!(first_link.plain_link, second_link.plain_link);
+
+    let subscriptions = query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].status, "pending_confirmation");
+
+    // The fresh link confirms the subscription
+    reqwest::get(second_link.plain_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn subscribing_a_confirmed_email_sends_a_notice_instead_of_a_new_link() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let link = ConfirmationLinks::get_confirmation_link(&requests[0], app.port);
+    reqwest::get(link.plain_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(email["Subject"], "You are already subscribed");
+    assert!(
+        !email["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains("subscription_token")
+    );
+
+    let subscription = query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscription.status, "confirmed");
+    let tokens = query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+}
+
+#[actix_web::test]
+async fn subscribing_again_after_unsubscribing_requires_confirmation() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let link = ConfirmationLinks::get_confirmation_link(&requests[1], app.port);
+    let subscription = query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscription.status, "pending_confirmation");
+
+    reqwest::get(link.plain_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn concurrent_subscriptions_of_the_same_email_get_the_same_response() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let (first, second) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+
+    let subscriptions = query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].status, "pending_confirmation");
+}
+
+#[actix_web::test]
+async fn conflicting_subscriptions_get_the_same_response_as_successful_ones() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Storing the second confirmation token now violates a unique constraint, as if another
+    // request had stored it first.
+    query!("CREATE UNIQUE INDEX ON subscription_tokens (subscriber_id)")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().is_empty());
+}
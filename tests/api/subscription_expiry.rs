@@ -0,0 +1,89 @@
+use zero2prod::subscription_expiry_workers::delete_expired_subscriptions;
+
+use crate::helpers::{
+    TestApp, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+};
+
+async fn backdate_subscriptions(app: &TestApp, status: &str) {
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - interval '8 days' WHERE status = $1",
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET created_at = now() - interval '8 days'
+        WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE status = $1)
+        "#,
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn count_subscriptions(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+async fn count_tokens(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[actix_web::test]
+async fn subscribers_who_never_confirmed_are_deleted() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    backdate_subscriptions(&app, "pending_confirmation").await;
+
+    delete_expired_subscriptions(&app.db_pool, &app.subscription_settings)
+        .await
+        .unwrap();
+
+    assert_eq!(count_subscriptions(&app).await, 0);
+    assert_eq!(count_tokens(&app).await, 0);
+}
+
+#[actix_web::test]
+async fn confirmed_and_recent_subscribers_are_kept() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    backdate_subscriptions(&app, "confirmed").await;
+    create_unconfirmed_subscriber(&app).await;
+
+    delete_expired_subscriptions(&app.db_pool, &app.subscription_settings)
+        .await
+        .unwrap();
+
+    assert_eq!(count_subscriptions(&app).await, 2);
+    // The confirmed subscriber's token has expired, the pending one's has not
+    assert_eq!(count_tokens(&app).await, 1);
+}
+
+#[actix_web::test]
+async fn pending_subscribers_who_asked_for_a_new_link_are_kept() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    backdate_subscriptions(&app, "pending_confirmation").await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    delete_expired_subscriptions(&app.db_pool, &app.subscription_settings)
+        .await
+        .unwrap();
+
+    assert_eq!(count_subscriptions(&app).await, 1);
+    assert_eq!(count_tokens(&app).await, 1);
+}
@@ -0,0 +1,106 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, insert_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+async fn publish(app: &TestApp, html: &str, text: &str) -> reqwest::Response {
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "html": html,
+        "text": text,
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await
+}
+
+async fn delivered_email(app: &TestApp) -> serde_json::Value {
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&request.body).unwrap()
+}
+
+#[actix_web::test]
+async fn issues_are_personalized_for_each_recipient() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "ursula@example.com", "<Ursula>").await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = publish(
+        &app,
+        r#"<p>Hi {{ name }}</p><a href="{{ unsubscribe_url }}">Leave</a>"#,
+        "Hi {{ name }}, this was sent to {{ email }}. Web version: {{ view_in_browser_url }}",
+    )
+    .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let email = delivered_email(&app).await;
+    let html = email["HtmlBody"].as_str().unwrap();
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(html.contains("<p>Hi &lt;Ursula&gt;</p>"));
+    assert!(html.contains(&format!(
+        r#"<a href="{}/subscription/unsubscribe?token="#,
+        app.base_url
+    )));
+    assert!(text.contains("Hi <Ursula>, this was sent to ursula@example.com."));
+    assert!(text.contains(&format!("Web version: {}/issues/", app.base_url)));
+}
+
+#[actix_web::test]
+async fn unknown_variables_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = publish(&app, "<p>Hi {{ first_name }}</p>", "Hi").await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = publish(&app, "<p>Hi</p>", "Hi {{ name").await;
+    assert_eq!(response.status().as_u16(), 400);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_web::test]
+async fn the_web_view_uses_the_fallbacks() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = publish(
+        &app,
+        r#"<p>Hi {{ name | "reader" }}{{ email }}</p>"#,
+        "Hi {{ name }}",
+    )
+    .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let html = app
+        .get_issue(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p>Hi reader</p>"));
+}
@@ -0,0 +1,160 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, insert_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+const HTML: &str = r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a>
+    or <a href="{{ unsubscribe_url }}">leave</a>.</p>"#;
+
+async fn publish(app: &TestApp, track: bool) -> Uuid {
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut form = serde_json::json!({
+        "title": "Newsletter title",
+        "html": HTML,
+        "text": "Read the post",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    if track {
+        form["track_opens"] = "true".into();
+        form["track_clicks"] = "true".into();
+    }
+    let response = app.post_newsletters(form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn delivered_html(app: &TestApp) -> String {
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// The tracking links of the html part, with the port of the test server.
+fn tracking_links(html: &str, route: &str, port: u16) -> Vec<reqwest::Url> {
+    linkify::LinkFinder::new()
+        .links(html)
+        .filter(|l| l.as_str().contains(route))
+        .map(|l| {
+            let mut link = reqwest::Url::parse(l.as_str()).unwrap();
+            link.set_port(Some(port)).unwrap();
+            link
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn opens_and_clicks_are_tracked_when_the_issue_opts_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+
+    let issue_id = publish(&app, true).await;
+
+    let html = delivered_html(&app).await;
+    assert!(!html.contains(r#"href="https://example.com/post"#));
+    // Unsubscribing is not counted as a click.
+    let unsubscribe_link = format!(r#"href="{}/subscription/unsubscribe?token="#, app.base_url);
+    assert_eq!(html.matches(&unsubscribe_link).count(), 2);
+
+    let pixels = tracking_links(&html, "/t/o/", app.port);
+    assert_eq!(pixels.len(), 1);
+    let response = app.get_tracking_link(pixels[0].clone()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+
+    let clicks = tracking_links(&html, "/t/c/", app.port);
+    assert_eq!(clicks.len(), 1);
+    for _ in 0..2 {
+        let response = app.get_tracking_link(clicks[0].clone()).await;
+        assert_eq!(response.status().as_u16(), 302);
+        assert_eq!(
+            response.headers()["Location"],
+            "https://example.com/post?a=1&b=2"
+        );
+    }
+
+    let report = app.get_newsletter_report_html(issue_id).await;
+    assert!(report.contains("<th>Open rate</th><td>100.0% (1 subscribers)</td>"));
+    assert!(report.contains("<th>Click rate</th><td>100.0% (1 subscribers)</td>"));
+    assert!(report.contains("<td>https://example.com/post?a=1&amp;b=2</td><td>2</td><td>1</td>"));
+}
+
+#[actix_web::test]
+async fn issues_are_not_tracked_unless_they_opt_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+
+    let issue_id = publish(&app, false).await;
+
+    let html = delivered_html(&app).await;
+    assert!(html.contains(r#"href="https://example.com/post?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/"));
+    let report = app.get_newsletter_report_html(issue_id).await;
+    assert!(report.contains("This issue does not track opens or clicks."));
+}
+
+#[actix_web::test]
+async fn tracking_can_be_disabled_for_every_issue() {
+    let mut app = spawn_app().await;
+    app.delivery_settings.tracking_enabled = false;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+
+    publish(&app, true).await;
+
+    let html = delivered_html(&app).await;
+    assert!(html.contains(r#"href="https://example.com/post?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/"));
+}
+
+#[actix_web::test]
+async fn tampered_tracking_tokens_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, "ursula@example.com", "Ursula").await;
+    publish(&app, true).await;
+    let html = delivered_html(&app).await;
+    let pixel = tracking_links(&html, "/t/o/", app.port).pop().unwrap();
+
+    // An open token does not lead anywhere.
+    let open_token = pixel.path_segments().unwrap().next_back().unwrap();
+    for path in [
+        format!("/t/c/{open_token}"),
+        format!("/t/o/{}.{}.00", Uuid::new_v4(), Uuid::new_v4()),
+        "/t/c/not-a-token".to_string(),
+    ] {
+        let response = app
+            .api_client
+            .get(format!("{}{path}", app.address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 400, "{path}");
+    }
+    let n_events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_tracking_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 0);
+}
@@ -0,0 +1,286 @@
+use chrono::TimeDelta;
+use secrecy::Secret;
+use zero2prod::authentication::Totp;
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+/// What authenticator apps show for `secret` at the time of the app's clock.
+fn current_code(app: &TestApp, secret: &str) -> String {
+    Totp::new(&Secret::new(secret.to_string()), "")
+        .unwrap()
+        .generate(app.clock.now())
+}
+
+fn secret_from_page(html_page: &str) -> String {
+    html_page
+        .split("enter this key in the app instead: <code>")
+        .nth(1)
+        .and_then(|rest| rest.split("</code>").next())
+        .expect("The page does not show the secret")
+        .to_string()
+}
+
+fn recovery_codes_from_page(html_page: &str) -> Vec<String> {
+    html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|rest| rest.split("</code>").next().unwrap().to_string())
+        .collect()
+}
+
+/// Set up an authenticator for the test user, who must be logged in, and return its secret and
+/// the recovery codes.
+async fn set_up_authenticator(app: &TestApp) -> (String, Vec<String>) {
+    let secret = secret_from_page(&app.get_two_factor_html().await);
+    let response = app.post_two_factor("", &current_code(app, &secret)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let codes = recovery_codes_from_page(&response.text().await.unwrap());
+    // Codes are accepted once, so the next one is needed to log in again.
+    app.clock.advance(TimeDelta::seconds(30));
+    (secret, codes)
+}
+
+/// Log out and enter the password of the test user again, which asks for the second factor.
+async fn log_in_again(app: &TestApp) {
+    app.post_logout().await;
+    let response = app
+        .post_login(serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+async fn get_second_factor_html(app: &TestApp) -> String {
+    app.get_second_factor().await.text().await.unwrap()
+}
+
+#[actix_web::test]
+async fn an_authenticator_is_only_turned_on_with_one_of_its_codes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains(r#"<img src="data:image/svg+xml;base64,"#));
+    let secret = secret_from_page(&html_page);
+    assert!(html_page.contains(&format!(
+        "otpauth://totp/zero2prod:{}?secret={secret}",
+        app.test_user.username
+    )));
+
+    let response = app.post_two_factor("", "000000").await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("The code you entered is not valid."));
+    // The same authenticator is shown until it is confirmed.
+    assert_eq!(secret_from_page(&html_page), secret);
+    let secret_in_db = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.uuid
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret;
+    assert!(secret_in_db.is_none());
+
+    let response = app.post_two_factor("", &current_code(&app, &secret)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let codes = recovery_codes_from_page(&response.text().await.unwrap());
+    assert_eq!(codes.len(), 10);
+
+    // The session the authenticator was set up in stays logged in.
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is on, with 10 recovery codes left."));
+}
+
+#[actix_web::test]
+async fn turning_on_an_authenticator_logs_out_other_sessions() {
+    let app = spawn_app().await;
+    let other_session = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = other_session
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.test_user.login(&app).await;
+
+    set_up_authenticator(&app).await;
+
+    let response = other_session
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn the_password_alone_is_not_enough_once_an_authenticator_is_on() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = set_up_authenticator(&app).await;
+
+    log_in_again(&app).await;
+    for path in ["/admin/dashboard", "/admin/two_factor", "/admin/users"] {
+        let response = app
+            .api_client
+            .get(format!("{}{path}", app.address))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/login/two_factor");
+    }
+    let html_page = get_second_factor_html(&app).await;
+    assert!(html_page.contains("Enter the code shown by your authenticator app"));
+
+    let response = app.post_second_factor(&current_code(&app, &secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn codes_are_rejected_once_used_or_too_old() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = set_up_authenticator(&app).await;
+
+    // The code the authenticator was confirmed with, which is still within the skew.
+    let used_code = Totp::new(&Secret::new(secret.clone()), "")
+        .unwrap()
+        .generate(app.clock.now() - TimeDelta::seconds(30));
+    log_in_again(&app).await;
+    let response = app.post_second_factor(&used_code).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    assert!(
+        get_second_factor_html(&app)
+            .await
+            .contains("The code you entered is not valid.")
+    );
+
+    let old_code = current_code(&app, &secret);
+    app.clock.advance(TimeDelta::seconds(90));
+    let response = app.post_second_factor(&old_code).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    let code = current_code(&app, &secret);
+    let response = app.post_second_factor(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Someone who saw the code cannot log in with it.
+    log_in_again(&app).await;
+    let response = app.post_second_factor(&code).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[actix_web::test]
+async fn each_recovery_code_works_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, codes) = set_up_authenticator(&app).await;
+
+    log_in_again(&app).await;
+    // They are accepted however they are typed.
+    let response = app
+        .post_second_factor(&codes[3].to_uppercase().replace('-', " "))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You logged in with a recovery code, 9 left."));
+
+    log_in_again(&app).await;
+    let response = app.post_second_factor(&codes[3]).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.post_second_factor(&codes[4]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[actix_web::test]
+async fn new_recovery_codes_replace_the_previous_ones() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, old_codes) = set_up_authenticator(&app).await;
+
+    let response = app.post_two_factor("/recovery_codes", "000000").await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    assert!(
+        app.get_two_factor_html()
+            .await
+            .contains("The code you entered is not valid.")
+    );
+
+    let response = app
+        .post_two_factor("/recovery_codes", &current_code(&app, &secret))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_codes = recovery_codes_from_page(&response.text().await.unwrap());
+    assert_eq!(new_codes.len(), 10);
+
+    log_in_again(&app).await;
+    let response = app.post_second_factor(&old_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.post_second_factor(&new_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[actix_web::test]
+async fn too_many_invalid_codes_require_the_password_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = set_up_authenticator(&app).await;
+
+    log_in_again(&app).await;
+    for _ in 0..4 {
+        let response = app.post_second_factor("000000").await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+    }
+    let response = app.post_second_factor("000000").await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many invalid codes, log in again."));
+
+    let response = app.post_second_factor(&current_code(&app, &secret)).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_second_factor().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn turning_off_the_authenticator_needs_a_code() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, codes) = set_up_authenticator(&app).await;
+
+    let response = app.post_two_factor("/disable", "000000").await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    assert!(
+        app.get_two_factor_html()
+            .await
+            .contains("The code you entered is not valid.")
+    );
+
+    // A recovery code will do, for users who lost their authenticator.
+    let response = app.post_two_factor("/disable", &codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is off."));
+    assert!(html_page.contains("enter this key in the app instead"));
+
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+}
@@ -0,0 +1,168 @@
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{TestApp, create_confirmed_subscriber, spawn_app, when_sending_an_email};
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+    "title": "Newsletter title",
+    "text": "Newsletter body as plain text",
+    "html": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn get_delivered_newsletter(app: &TestApp) -> serde_json::Value {
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&request.body).unwrap()
+}
+
+fn get_unsubscribe_link(s: &str, port: u16) -> reqwest::Url {
+    let links: Vec<_> = linkify::LinkFinder::new()
+        .links(s)
+        .filter(|l| l.as_str().contains("/subscription/unsubscribe"))
+        .collect();
+    assert_eq!(links.len(), 1);
+    let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+    link.set_port(Some(port)).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link
+}
+
+#[actix_web::test]
+async fn delivered_newsletters_contain_an_unsubscribe_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+
+    let body = get_delivered_newsletter(&app).await;
+    let html_link = get_unsubscribe_link(body["HtmlBody"].as_str().unwrap(), app.port);
+    let text_link = get_unsubscribe_link(body["TextBody"].as_str().unwrap(), app.port);
+    assert_eq!(html_link, text_link);
+
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(|h| {
+        h["Name"] == "List-Unsubscribe"
+            && h["Value"]
+                .as_str()
+                .unwrap()
+                .contains("/subscription/unsubscribe?token=")
+    }));
+    assert!(headers.iter().any(
+        |h| h["Name"] == "List-Unsubscribe-Post" && h["Value"] == "List-Unsubscribe=One-Click"
+    ));
+}
+
+#[actix_web::test]
+async fn unsubscribe_link_returns_a_confirmation_page() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    let body = get_delivered_newsletter(&app).await;
+    let link = get_unsubscribe_link(body["TextBody"].as_str().unwrap(), app.port);
+
+    let response = app.get_unsubscribe(link).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<form action="/subscription/unsubscribe?token="#));
+
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status.status, "confirmed");
+}
+
+#[actix_web::test]
+async fn one_click_unsubscribe_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    let body = get_delivered_newsletter(&app).await;
+    let link = get_unsubscribe_link(body["TextBody"].as_str().unwrap(), app.port);
+
+    let response = app.post_unsubscribe(link).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status.status, "unsubscribed");
+}
+
+#[actix_web::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    let body = get_delivered_newsletter(&app).await;
+    let link = get_unsubscribe_link(body["TextBody"].as_str().unwrap(), app.port);
+    drop(guard);
+
+    app.post_unsubscribe(link).await.error_for_status().unwrap();
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+}
+
+#[actix_web::test]
+async fn tampered_unsubscribe_tokens_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let token = format!("{}.{}", uuid::Uuid::new_v4(), "ab".repeat(32));
+    let link = reqwest::Url::parse(&format!(
+        "{}/subscription/unsubscribe?token={token}",
+        app.address
+    ))
+    .unwrap();
+
+    let response = app.get_unsubscribe(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.post_unsubscribe(link).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
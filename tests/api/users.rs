@@ -0,0 +1,328 @@
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    ConfirmationLinks, TestApp, TestUser, assert_is_redirect_to, spawn_app, when_sending_an_email,
+};
+
+const EMAIL: &str = "ursula@example.com";
+
+/// Log a user in with a client of their own, so that the test user stays logged in as well.
+async fn log_in_elsewhere(app: &TestApp, user: &TestUser) -> (reqwest::Client, reqwest::Response) {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": user.username,
+            "password": user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    (client, response)
+}
+
+async fn get_dashboard(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Invite `EMAIL` and return the link from the invitation.
+async fn invite(app: &TestApp, role: &str) -> reqwest::Url {
+    let _guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_invite_user(serde_json::json!({"email": EMAIL, "role": role}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let links = ConfirmationLinks::get_confirmation_link(requests.last().unwrap(), app.port);
+    assert_eq!(links.html_link, links.plain_link);
+    links.html_link
+}
+
+fn accept_form(link: &reqwest::Url, password: &str, confirm_password: &str) -> serde_json::Value {
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    serde_json::json!({
+        "token": token,
+        "username": "ursula",
+        "password": password,
+        "confirm_password": confirm_password
+    })
+}
+
+#[actix_web::test]
+async fn only_admins_can_manage_users() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    let response = app.get_users().await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app
+        .post_invite_user(serde_json::json!({"email": EMAIL, "role": "admin"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.post_user_action(app.test_user.uuid, "disable").await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let n_invitations = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM user_invitations"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_invitations, 0);
+}
+
+#[actix_web::test]
+async fn invited_users_choose_their_credentials_and_can_log_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let link = invite(&app, "editor").await;
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("An invitation has been sent to ursula@example.com."));
+    assert!(html_page.contains("<td>ursula@example.com</td><td>editor</td>"));
+
+    let response = app.get_invitation(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("You have been invited as editor with ursula@example.com."));
+
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_invitation(accept_form(&link, &password, &password))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your account has been created, you can now log in."));
+
+    let user = sqlx::query!("SELECT user_id, email, role FROM users WHERE username = 'ursula'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.email.as_deref(), Some(EMAIL));
+    assert_eq!(user.role, "editor");
+    let user = TestUser {
+        uuid: user.user_id,
+        username: "ursula".into(),
+        password,
+        role: "editor",
+    };
+    let (client, response) = log_in_elsewhere(&app, &user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = get_dashboard(&app, &client).await.text().await.unwrap();
+    assert!(html_page.contains("Welcome ursula (editor)"));
+
+    // Each link works once.
+    let response = app.get_invitation(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_invitation(accept_form(&link, &user.password, &user.password))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_web::test]
+async fn invalid_passwords_are_rejected_and_the_link_keeps_working() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "viewer").await;
+    let form_url = format!("/invitation?{}", link.query().unwrap());
+
+    let cases = [
+        (
+            accept_form(&link, "a-long-enough-password", "another-long-password"),
+            "You entered two different passwords - the field values must match.",
+        ),
+        (
+            accept_form(&link, "too-short", "too-short"),
+            "The new password you entered must be at least 12 characters long.",
+        ),
+    ];
+    for (form, message) in cases {
+        let response = app.post_invitation(form).await;
+        assert_is_redirect_to(&response, &form_url);
+        let html_page = app.get_invitation(link.clone()).await.text().await.unwrap();
+        assert!(html_page.contains(message), "{message}");
+    }
+
+    let user = sqlx::query!("SELECT user_id FROM users WHERE username = 'ursula'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(user.is_none());
+
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_invitation(accept_form(&link, &password, &password))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn inviting_again_replaces_the_previous_link() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let first_link = invite(&app, "viewer").await;
+    let second_link = invite(&app, "admin").await;
+
+    let response = app.get_invitation(first_link).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = app.get_invitation(second_link).await.text().await.unwrap();
+    assert!(html_page.contains("You have been invited as admin"));
+}
+
+#[actix_web::test]
+async fn expired_invitations_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "viewer").await;
+
+    sqlx::query!("UPDATE user_invitations SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_invitation(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 410);
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_invitation(accept_form(&link, &password, &password))
+        .await;
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("expired, invite them again"));
+}
+
+#[actix_web::test]
+async fn addresses_of_existing_users_cannot_be_invited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        EMAIL,
+        app.test_user.uuid
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_invite_user(serde_json::json!({"email": "Ursula@Example.com", "role": "viewer"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("This email address is already used by another user."));
+}
+
+#[actix_web::test]
+async fn disabled_users_are_logged_out_until_they_are_enabled_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let (client, _) = log_in_elsewhere(&app, &viewer).await;
+
+    let response = app.post_user_action(viewer.uuid, "disable").await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains(&format!("{} has been disabled.", viewer.username)));
+
+    let response = get_dashboard(&app, &client).await;
+    assert_is_redirect_to(&response, "/login");
+    let (_, response) = log_in_elsewhere(&app, &viewer).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_user_action(viewer.uuid, "enable").await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let (client, response) = log_in_elsewhere(&app, &viewer).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = get_dashboard(&app, &client).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn deleting_a_user_deletes_their_idempotency_keys() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    let (client, _) = log_in_elsewhere(&app, &editor).await;
+
+    let response = client
+        .post(format!("{}/admin/newsletters", app.address))
+        .form(&serde_json::json!({
+            "title": "Newsletter title",
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let response = app.post_user_action(editor.uuid, "delete").await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let n_keys = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM idempotency WHERE user_id = $1"#,
+        editor.uuid
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_keys, 0);
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains(&format!("{} has been deleted.", editor.username)));
+    assert!(!html_page.contains(&format!("<td>{}</td>", editor.username)));
+
+    let response = get_dashboard(&app, &client).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn admins_cannot_disable_or_delete_themselves() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for (action, message) in [
+        ("disable", "You cannot disable your own account."),
+        ("delete", "You cannot delete your own account."),
+    ] {
+        let response = app.post_user_action(app.test_user.uuid, action).await;
+        assert_is_redirect_to(&response, "/admin/users");
+        let html_page = app.get_users_html().await;
+        assert!(html_page.contains(message), "{message}");
+    }
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
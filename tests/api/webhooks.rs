@@ -0,0 +1,276 @@
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, insert_confirmed_subscriber, spawn_app, when_sending_an_email,
+};
+
+const EMAIL: &str = "ursula@example.com";
+
+fn hard_bounce(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "MessageStream": "outbound",
+        "ID": 4323372036854775807_i64,
+        "Type": "HardBounce",
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Description": "The server was unable to deliver your message (ex: unknown user, mailbox not found).",
+        "Details": "smtp;550 5.1.1 The email account that you tried to reach does not exist.",
+        "Email": email,
+        "From": "sender@example.com",
+        "BouncedAt": "2026-10-18T16:00:00Z",
+        "Inactive": true,
+        "CanActivate": true,
+        "Subject": "Newsletter title"
+    })
+}
+
+fn soft_bounce(email: &str) -> serde_json::Value {
+    let mut event = hard_bounce(email);
+    event["Type"] = "SoftBounce".into();
+    event["TypeCode"] = 4096.into();
+    event["Inactive"] = false.into();
+    event
+}
+
+fn spam_complaint(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "MessageStream": "outbound",
+        "ID": 42,
+        "Type": "SpamComplaint",
+        "TypeCode": 512,
+        "Name": "Spam complaint",
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Email": email,
+        "From": "sender@example.com",
+        "BouncedAt": "2026-10-18T16:00:00Z",
+        "Inactive": true,
+        "CanActivate": false
+    })
+}
+
+fn reactivation(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SubscriptionChange",
+        "MessageStream": "outbound",
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "ChangedAt": "2026-10-18T17:00:00Z",
+        "Recipient": email,
+        "Origin": "Customer",
+        "SuppressSending": false,
+        "SuppressionReason": null
+    })
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn suppression_reason(app: &TestApp) -> Option<String> {
+    sqlx::query!("SELECT reason FROM suppressions WHERE email = $1", EMAIL)
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap()
+        .map(|r| r.reason)
+}
+
+async fn publish(app: &TestApp) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[actix_web::test]
+async fn events_without_valid_credentials_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    insert_confirmed_subscriber(&app, EMAIL, "Ursula").await;
+    let url = format!("{}/webhooks/email", app.address);
+    let client = reqwest::Client::new();
+
+    let requests = [
+        client.post(&url),
+        client
+            .post(&url)
+            .basic_auth(&app.webhook_settings.username, Some("wrong-secret")),
+        client.post(&url).basic_auth(
+            "someone-else",
+            Some(app.webhook_settings.secret.expose_secret()),
+        ),
+        client.post(&url).header("X-Webhook-Secret", "wrong-secret"),
+    ];
+    for request in requests {
+        let response = request.json(&hard_bounce(EMAIL)).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="webhooks""#
+        );
+    }
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+
+    let response = client
+        .post(&url)
+        .header(
+            "X-Webhook-Secret",
+            app.webhook_settings.secret.expose_secret(),
+        )
+        .json(&hard_bounce(EMAIL))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+}
+
+#[actix_web::test]
+async fn malformed_events_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for event in [
+        serde_json::json!({"Email": EMAIL}),
+        serde_json::json!({"RecordType": "Bounce", "Type": "HardBounce"}),
+        serde_json::json!({"RecordType": "SpamComplaint"}),
+    ] {
+        let response = app.post_email_webhook(&event).await;
+        assert_eq!(response.status().as_u16(), 400, "{event}");
+    }
+}
+
+#[actix_web::test]
+async fn other_events_are_acknowledged_and_ignored() {
+    let app = spawn_app().await;
+    insert_confirmed_subscriber(&app, EMAIL, "Ursula").await;
+
+    let delivery = serde_json::json!({
+        "RecordType": "Delivery",
+        "Recipient": EMAIL,
+        "DeliveredAt": "2026-10-18T16:00:00Z"
+    });
+    for event in [delivery, soft_bounce(EMAIL)] {
+        let response = app.post_email_webhook(&event).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(suppression_reason(&app).await, None);
+}
+
+#[actix_web::test]
+async fn queued_deliveries_to_a_hard_bounced_address_are_skipped() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, EMAIL, "Ursula").await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    publish(&app).await;
+    // Addresses are matched whatever their case.
+    let response = app
+        .post_email_webhook(&hard_bounce(&EMAIL.to_uppercase()))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(subscriber_status(&app).await, "bounced");
+    assert_eq!(
+        suppression_reason(&app).await.as_deref(),
+        Some("HardBounce")
+    );
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "skipped_suppressed");
+}
+
+#[actix_web::test]
+async fn suppressed_addresses_are_not_queued() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, EMAIL, "Ursula").await;
+    // Suppressed on Postmark's side while the subscriber is still confirmed here, e.g. a bounce
+    // from before the webhook was set up.
+    sqlx::query!(
+        "INSERT INTO suppressions (email, reason, suppressed_at) VALUES ($1, 'HardBounce', now())",
+        EMAIL
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    publish(&app).await;
+
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[actix_web::test]
+async fn spam_complaints_unsubscribe_the_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, EMAIL, "Ursula").await;
+
+    let response = app.post_email_webhook(&spam_complaint(EMAIL)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+    assert_eq!(
+        suppression_reason(&app).await.as_deref(),
+        Some("SpamComplaint")
+    );
+    let list_subscription = sqlx::query!(
+        "SELECT status FROM list_subscriptions WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(list_subscription.status, "unsubscribed");
+
+    // Reactivating the address does not subscribe them again.
+    app.post_email_webhook(&reactivation(EMAIL)).await;
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+    assert_eq!(suppression_reason(&app).await, None);
+}
+
+#[actix_web::test]
+async fn reactivated_addresses_receive_issues_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_confirmed_subscriber(&app, EMAIL, "Ursula").await;
+    app.post_email_webhook(&hard_bounce(EMAIL)).await;
+
+    let response = app.post_email_webhook(&reactivation(EMAIL)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(suppression_reason(&app).await, None);
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish(&app).await;
+    app.dispatch_all_pending_emails().await;
+}